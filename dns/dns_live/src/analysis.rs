@@ -0,0 +1,309 @@
+//! Telling how a student got to their answers: by walking the hierarchy, or by guessing.
+//!
+//! Every query in a session's log is classified by what its response was and which server gave it,
+//! and flagged if it went to a server that the student had no way of knowing about yet.
+//! The world of the session tells which addresses are root servers;
+//! responses stored before version 7 of the format cannot be analysed.
+
+use std::{collections::HashSet, net::IpAddr};
+
+use serde::Serialize;
+use trust_dns_client::{
+    op::{Message, ResponseCode},
+    rr::{RData, RecordType},
+};
+
+use crate::{
+    session::{Event, ResponseResult, Session},
+    world::World,
+};
+
+/// What a query amounted to.
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum QueryKind {
+    /// A root server referred the student to the servers of a TLD.
+    RootReferral,
+    /// A server referred the student further down, usually from a TLD to the servers of a domain.
+    TldReferral,
+    /// A server that is authoritative for the name answered, even if with no records.
+    AuthoritativeAnswer,
+    /// The name does not exist.
+    NxDomain,
+    /// There is no server at the address, or it refused to answer.
+    WrongServer,
+    /// The same query as an earlier one.
+    Repeat,
+    /// The address or the name was not valid.
+    Invalid,
+    /// The response was not stored in full.
+    Unknown,
+}
+
+impl QueryKind {
+    pub fn label(self) -> &'static str {
+        match self {
+            QueryKind::RootReferral => "root referral",
+            QueryKind::TldReferral => "TLD referral",
+            QueryKind::AuthoritativeAnswer => "authoritative answer",
+            QueryKind::NxDomain => "NXDOMAIN",
+            QueryKind::WrongServer => "wrong server",
+            QueryKind::Repeat => "repeat",
+            QueryKind::Invalid => "invalid",
+            QueryKind::Unknown => "unknown",
+        }
+    }
+}
+
+/// The analysis of one query.
+#[derive(Serialize, Debug)]
+pub struct QueryAnalysis {
+    /// Position of the query in the session's log.
+    pub entry: usize,
+    /// Index of the question the query was made for.
+    pub question: usize,
+    pub kind: QueryKind,
+    pub label: &'static str,
+    /// The server answered, but its address had not appeared in any earlier response,
+    /// and it is not a root server.
+    pub shortcut: bool,
+}
+
+/// Classify every query of a session, in the order of its log.
+pub fn analyse(world: &World, session: &Session) -> Vec<QueryAnalysis> {
+    let roots: HashSet<IpAddr> = world
+        .root_server_addresses()
+        .into_iter()
+        .map(IpAddr::from)
+        .collect();
+    let mut asked = HashSet::new();
+    let mut discovered = HashSet::new();
+    let mut analysis = vec![];
+    for (entry, item) in session.user_requests.iter().enumerate() {
+        let Event::Request {
+            question,
+            request,
+            response,
+        } = &item.what
+        else {
+            continue;
+        };
+        let server: Option<IpAddr> = request.server_ip.trim().parse().ok();
+        let to_root = server.is_some_and(|server| roots.contains(&server));
+        let message = match response {
+            ResponseResult::Ok { resp } => resp.message(),
+            _ => None,
+        };
+        let first_time = asked.insert((
+            request.server_ip.trim().to_owned(),
+            request.name.trim().trim_end_matches('.').to_lowercase(),
+            request.record_type,
+        ));
+
+        let kind = match (response, &message) {
+            _ if !first_time => QueryKind::Repeat,
+            (ResponseResult::Ok { .. }, Some(message)) => classify(message, to_root),
+            (ResponseResult::Ok { .. }, None) => QueryKind::Unknown,
+            (ResponseResult::QueryError { err }, _) if err.starts_with("InvalidName") => {
+                QueryKind::Invalid
+            }
+            (ResponseResult::QueryError { .. }, _) => QueryKind::WrongServer,
+            (ResponseResult::ForbiddenRecursion { .. }, _) => QueryKind::WrongServer,
+            (ResponseResult::InvalidRequestIpAddr { .. }, _) => QueryKind::Invalid,
+        };
+        let shortcut = match (&message, server) {
+            (Some(_), Some(server)) => !to_root && !discovered.contains(&server),
+            _ => false,
+        };
+        if let Some(message) = &message {
+            discovered.extend(addresses(message));
+        }
+        analysis.push(QueryAnalysis {
+            entry,
+            question: *question,
+            kind,
+            label: kind.label(),
+            shortcut,
+        });
+    }
+    analysis
+}
+
+/// The analysis of every query, at the position of the query in the session's log,
+/// with nothing at the positions of other events.
+pub fn by_entry(world: &World, session: &Session) -> Vec<Option<QueryAnalysis>> {
+    let mut entries: Vec<_> = session.user_requests.iter().map(|_| None).collect();
+    for query in analyse(world, session) {
+        let entry = query.entry;
+        entries[entry] = Some(query);
+    }
+    entries
+}
+
+/// How many queries of a session went to servers the student could not have known about.
+pub fn shortcut_count(world: &World, session: &Session) -> usize {
+    analyse(world, session)
+        .iter()
+        .filter(|query| query.shortcut)
+        .count()
+}
+
+/// What a response amounts to; `to_root` is whether the query went to one of the world's root servers.
+fn classify(message: &Message, to_root: bool) -> QueryKind {
+    match message.response_code() {
+        ResponseCode::NoError => {}
+        ResponseCode::NXDomain => return QueryKind::NxDomain,
+        _ => return QueryKind::WrongServer,
+    }
+    if message.authoritative() {
+        return QueryKind::AuthoritativeAnswer;
+    }
+    if !is_referral(message) {
+        // Neither authoritative nor a referral: the server has nothing to do with the name.
+        return QueryKind::WrongServer;
+    }
+    // Whatever the referral points to, only a root server's is the first step of the way down.
+    if to_root {
+        QueryKind::RootReferral
+    } else {
+        QueryKind::TldReferral
+    }
+}
+
+/// Whether a response sends the student to the servers of another zone.
+fn is_referral(message: &Message) -> bool {
+    message.answers().is_empty()
+        && message
+            .name_servers()
+            .iter()
+            .any(|record| record.rr_type() == RecordType::NS)
+}
+
+/// Addresses of servers that a response tells about.
+fn addresses(message: &Message) -> impl Iterator<Item = IpAddr> + '_ {
+    message
+        .answers()
+        .iter()
+        .chain(message.additionals())
+        .filter_map(|record| match record.data() {
+            Some(RData::A(addr)) => Some(IpAddr::from(*addr)),
+            Some(RData::AAAA(addr)) => Some(IpAddr::from(*addr)),
+            _ => None,
+        })
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::Utc;
+
+    use super::*;
+    use crate::{
+        session::{OutputMode, Request, RequestLogEntry, Response},
+        world::{parse_name, World, WorldData},
+    };
+
+    #[test]
+    fn test_analyse() {
+        let world = World::generate(&WorldData::builtin(), b"test");
+        let name = parse_name("www.wikipedia.org").unwrap();
+        let path = world.resolution_path(&name, RecordType::A);
+        assert_eq!(path.len(), 3);
+
+        let mut session = Session::new(vec![]);
+        let mut ask = |server: IpAddr, name: &str| {
+            let parsed = parse_name(name).unwrap();
+            let response = match world.query(server, &parsed, RecordType::A) {
+                Ok(message) => ResponseResult::Ok {
+                    resp: Response::new(&message, OutputMode::Classic),
+                },
+                Err(error) => ResponseResult::QueryError {
+                    err: error.to_string(),
+                },
+            };
+            session.user_requests.push(RequestLogEntry {
+                when: Utc::now(),
+                what: Event::Request {
+                    question: 0,
+                    request: Request {
+                        server_ip: server.to_string(),
+                        name: name.to_string(),
+                        record_type: RecordType::A,
+                    },
+                    response,
+                },
+            });
+        };
+        let root = path[0].server;
+        let tld = path[1].server;
+        let domain = path[2].server;
+        // Straight to the domain's servers, before anyone told about them.
+        ask(domain, "www.wikipedia.org");
+        ask(root, "www.wikipedia.org");
+        ask(tld, "www.wikipedia.org");
+        ask(domain, "WWW.wikipedia.org.");
+        ask(root, "nonexistent.invalid");
+        ask("192.0.2.1".parse().unwrap(), "www.wikipedia.org");
+
+        let analysis = analyse(&world, &session);
+        let kinds: Vec<QueryKind> = analysis.iter().map(|query| query.kind).collect();
+        assert_eq!(
+            kinds,
+            [
+                QueryKind::AuthoritativeAnswer,
+                QueryKind::RootReferral,
+                QueryKind::TldReferral,
+                QueryKind::Repeat,
+                QueryKind::NxDomain,
+                QueryKind::WrongServer,
+            ]
+        );
+        let shortcuts: Vec<bool> = analysis.iter().map(|query| query.shortcut).collect();
+        assert_eq!(shortcuts, [true, false, false, false, false, false]);
+        assert_eq!(shortcut_count(&world, &session), 1);
+    }
+
+    #[test]
+    fn test_referral_from_non_root() {
+        let world = World::generate(&WorldData::builtin(), b"test");
+        let name = parse_name("www.wikipedia.org").unwrap();
+        let path = world.resolution_path(&name, RecordType::A);
+        let root = path[0].server;
+        let tld = path[1].server;
+        let root_referral = world.query(root, &name, RecordType::A).unwrap();
+
+        let mut session = Session::new(vec![]);
+        let mut log = |server: IpAddr, message: &Message| {
+            session.user_requests.push(RequestLogEntry {
+                when: Utc::now(),
+                what: Event::Request {
+                    question: 0,
+                    request: Request {
+                        server_ip: server.to_string(),
+                        name: name.to_string(),
+                        record_type: RecordType::A,
+                    },
+                    response: ResponseResult::Ok {
+                        resp: Response::new(message, OutputMode::Classic),
+                    },
+                },
+            });
+        };
+        // A TLD server that nobody told about yet, and an unknown server that returns
+        // the same referral as a root server: neither is a root referral, both are shortcuts.
+        log(tld, &world.query(tld, &name, RecordType::A).unwrap());
+        log("192.0.2.1".parse().unwrap(), &root_referral);
+        log(root, &root_referral);
+
+        let analysis = analyse(&world, &session);
+        let kinds: Vec<QueryKind> = analysis.iter().map(|query| query.kind).collect();
+        assert_eq!(
+            kinds,
+            [
+                QueryKind::TldReferral,
+                QueryKind::TldReferral,
+                QueryKind::RootReferral,
+            ]
+        );
+        let shortcuts: Vec<bool> = analysis.iter().map(|query| query.shortcut).collect();
+        assert_eq!(shortcuts, [true, true, false]);
+    }
+}
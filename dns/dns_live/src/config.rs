@@ -0,0 +1,217 @@
+//! Server configuration, read from a TOML file and overridden by environment variables and flags.
+
+use std::{
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use clap::Args;
+use serde::Deserialize;
+
+use crate::{
+    password::is_valid_hash,
+    session::{FileStore, SessionStore, SqliteStore},
+};
+
+/// The file that is read if no configuration file is given explicitly.
+const DEFAULT_CONFIG_FILE: &str = "dns_live.toml";
+
+#[derive(Deserialize, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Addresses to listen on. IPv6 addresses only accept IPv6 connections,
+    /// so list both `0.0.0.0:5000` and `[::]:5000` to serve both.
+    pub listen: Vec<SocketAddr>,
+    /// Directory with the session files
+    pub sessions_dir: PathBuf,
+    /// SQLite database to keep sessions in, instead of the sessions directory
+    pub session_database: Option<PathBuf>,
+    /// Secret that session worlds are derived from, as produced by `make secret`
+    pub secret_file: PathBuf,
+    /// Generate worlds from session keys alone if the secret cannot be read.
+    /// Students could then reproduce each other's worlds, so this is only for trying things out.
+    pub allow_missing_secret: bool,
+    /// Root zone file, as produced by `make update`
+    pub root_zone: PathBuf,
+    /// Majestic Million domain list, as produced by `make update`
+    pub domain_list: PathBuf,
+    /// Hash of the password for the teacher's pages under `/admin`, made with `dns_live hash-password`.
+    /// If not set, those pages are disabled.
+    pub admin_password_hash: Option<String>,
+    /// How many session worlds to keep in memory.
+    /// Best set to at least the number of students working at the same time.
+    pub world_cache_size: usize,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            listen: vec!["0.0.0.0:5000".parse().unwrap()],
+            sessions_dir: "sessions".into(),
+            session_database: None,
+            secret_file: "../secret.bin".into(),
+            allow_missing_secret: false,
+            root_zone: "../root.zone".into(),
+            domain_list: "../majestic_million.csv".into(),
+            admin_password_hash: None,
+            world_cache_size: 64,
+        }
+    }
+}
+
+/// Command-line flags (and the matching environment variables) that override the configuration file.
+#[derive(Args, Debug)]
+pub struct ConfigArgs {
+    /// Configuration file [default: dns_live.toml, if it exists]
+    #[arg(long, global = true, env = "DNS_LIVE_CONFIG")]
+    config: Option<PathBuf>,
+    /// Addresses to listen on, comma-separated
+    #[arg(long, global = true, env = "DNS_LIVE_LISTEN", value_delimiter = ',')]
+    listen: Vec<SocketAddr>,
+    /// Directory with the session files
+    #[arg(long, global = true, env = "DNS_LIVE_SESSIONS_DIR")]
+    sessions_dir: Option<PathBuf>,
+    /// SQLite database to keep sessions in, instead of the sessions directory
+    #[arg(long, global = true, env = "DNS_LIVE_SESSION_DATABASE")]
+    session_database: Option<PathBuf>,
+    /// Secret file that session worlds are derived from
+    #[arg(long, global = true, env = "DNS_LIVE_SECRET_FILE")]
+    secret_file: Option<PathBuf>,
+    /// Generate worlds from session keys alone if the secret cannot be read
+    #[arg(long, global = true, env = "DNS_LIVE_ALLOW_MISSING_SECRET")]
+    allow_missing_secret: bool,
+    /// Root zone file
+    #[arg(long, global = true, env = "DNS_LIVE_ROOT_ZONE")]
+    root_zone: Option<PathBuf>,
+    /// Majestic Million domain list
+    #[arg(long, global = true, env = "DNS_LIVE_DOMAIN_LIST")]
+    domain_list: Option<PathBuf>,
+    /// Hash of the password for the teacher's pages under /admin, see `hash-password`
+    #[arg(long, global = true, env = "DNS_LIVE_ADMIN_PASSWORD_HASH")]
+    admin_password_hash: Option<String>,
+    /// How many session worlds to keep in memory
+    #[arg(long, global = true, env = "DNS_LIVE_WORLD_CACHE_SIZE")]
+    world_cache_size: Option<usize>,
+}
+
+impl Config {
+    /// Read the configuration file, if any, and apply the overrides on top of it.
+    pub fn load(args: ConfigArgs) -> Result<Self, String> {
+        let mut config = match &args.config {
+            Some(path) => Self::from_file(path)?,
+            None if Path::new(DEFAULT_CONFIG_FILE).exists() => {
+                Self::from_file(Path::new(DEFAULT_CONFIG_FILE))?
+            }
+            None => Self::default(),
+        };
+
+        if !args.listen.is_empty() {
+            config.listen = args.listen;
+        }
+        if let Some(dir) = args.sessions_dir {
+            config.sessions_dir = dir;
+        }
+        if args.session_database.is_some() {
+            config.session_database = args.session_database;
+        }
+        if let Some(path) = args.secret_file {
+            config.secret_file = path;
+        }
+        if args.allow_missing_secret {
+            config.allow_missing_secret = true;
+        }
+        if let Some(path) = args.root_zone {
+            config.root_zone = path;
+        }
+        if let Some(path) = args.domain_list {
+            config.domain_list = path;
+        }
+        if args.admin_password_hash.is_some() {
+            config.admin_password_hash = args.admin_password_hash;
+        }
+        if let Some(size) = args.world_cache_size {
+            config.world_cache_size = size;
+        }
+        if let Some(hash) = &config.admin_password_hash {
+            if !is_valid_hash(hash) {
+                return Err(format!(
+                    "admin_password_hash is not a password hash: {hash:?}; make one with `dns_live hash-password`"
+                ));
+            }
+        }
+        Ok(config)
+    }
+
+    /// Open the configured session storage.
+    pub fn session_store(&self) -> Result<Arc<dyn SessionStore>, String> {
+        match &self.session_database {
+            Some(path) => match SqliteStore::open(path) {
+                Ok(store) => Ok(Arc::new(store)),
+                Err(e) => Err(format!("Could not open session database {path:?}: {e}")),
+            },
+            None => Ok(Arc::new(FileStore::new(&self.sessions_dir))),
+        }
+    }
+
+    /// Parse a configuration file.
+    /// Relative paths inside it are taken relative to the directory the file is in,
+    /// so that it works no matter what the working directory is.
+    fn from_file(path: &Path) -> Result<Self, String> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| format!("Could not read config file {path:?}: {e}"))?;
+        let mut config: Config =
+            toml::from_str(&text).map_err(|e| format!("Invalid config file {path:?}: {e}"))?;
+
+        let base = path.parent().unwrap_or(Path::new(""));
+        for field in [
+            &mut config.sessions_dir,
+            &mut config.secret_file,
+            &mut config.root_zone,
+            &mut config.domain_list,
+        ] {
+            if field.is_relative() {
+                *field = base.join(&*field);
+            }
+        }
+        if let Some(path) = &mut config.session_database {
+            if path.is_relative() {
+                *path = base.join(&*path);
+            }
+        }
+        Ok(config)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_config_file() {
+        let dir = std::env::temp_dir().join(format!("dns_live_config_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("dns_live.toml");
+        std::fs::write(
+            &path,
+            r#"
+            listen = ["127.0.0.1:8080", "[::1]:8080"]
+            sessions_dir = "data/sessions"
+            secret_file = "/etc/dns_live/secret.bin"
+            "#,
+        )
+        .unwrap();
+
+        let config = Config::from_file(&path).unwrap();
+        assert_eq!(config.listen.len(), 2);
+        assert_eq!(config.sessions_dir, dir.join("data/sessions"));
+        assert_eq!(config.secret_file, Path::new("/etc/dns_live/secret.bin"));
+        assert_eq!(config.root_zone, dir.join("../root.zone"));
+
+        std::fs::write(&path, "listen_on = []").unwrap();
+        assert!(Config::from_file(&path).is_err());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,339 @@
+//! Exporting the grades of all sessions as a table, and the history of a session as a trace.
+
+use chrono::{DateTime, Utc};
+use clap::ValueEnum;
+use minijinja::context;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    analysis::{by_entry, shortcut_count},
+    session::{QuestionStatus, Session, SessionError, SessionKey, SessionStore},
+    web::templates::env,
+    world::{World, WorldCache},
+};
+
+/// Separator between the values of several questions in one cell.
+const SEPARATOR: &str = " | ";
+
+#[derive(Serialize, Deserialize, ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    #[default]
+    Csv,
+    Json,
+}
+
+impl ExportFormat {
+    pub fn content_type(self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "text/csv; charset=utf-8",
+            ExportFormat::Json => "application/json",
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "csv",
+            ExportFormat::Json => "json",
+        }
+    }
+}
+
+/// How to write out the history of a session.
+#[derive(Serialize, Deserialize, ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum TraceFormat {
+    /// A standalone page for people to read
+    #[default]
+    Html,
+    /// One log entry per line, as JSON
+    Jsonl,
+}
+
+impl TraceFormat {
+    pub fn content_type(self) -> &'static str {
+        match self {
+            TraceFormat::Html => "text/html; charset=utf-8",
+            TraceFormat::Jsonl => "application/jsonl",
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            TraceFormat::Html => "html",
+            TraceFormat::Jsonl => "jsonl",
+        }
+    }
+}
+
+/// The grades of one session.
+/// The questions and their statuses are joined with " | ", in the order of the questions.
+#[derive(Serialize, Debug, PartialEq)]
+pub struct GradeRow {
+    pub key: SessionKey,
+    pub questions: String,
+    pub status: String,
+    pub attempts_used: usize,
+    pub queries: usize,
+    /// Queries to servers the student could not have known about.
+    pub shortcuts: usize,
+    pub first_activity: Option<DateTime<Utc>>,
+    pub last_activity: Option<DateTime<Utc>>,
+    pub score: f64,
+    pub max_score: f64,
+}
+
+impl GradeRow {
+    pub fn new(key: SessionKey, session: &Session, world: &World) -> Self {
+        let (score, max_score) = session.total_score();
+        Self {
+            key,
+            questions: session
+                .questions
+                .iter()
+                .map(|question| question.text.as_str())
+                .collect::<Vec<_>>()
+                .join(SEPARATOR),
+            status: session
+                .questions
+                .iter()
+                .map(|question| status_label(question.status))
+                .collect::<Vec<_>>()
+                .join(SEPARATOR),
+            attempts_used: session.attempts_used(),
+            queries: session.query_count(),
+            shortcuts: shortcut_count(world, session),
+            first_activity: session.user_requests.first().map(|entry| entry.when),
+            last_activity: session.user_requests.last().map(|entry| entry.when),
+            score,
+            max_score,
+        }
+    }
+
+    /// A row for a session that could not be read, so that it does not silently go missing.
+    fn unreadable(key: SessionKey, error: &SessionError) -> Self {
+        tracing::error!("Could not read session {key}: {error}");
+        Self {
+            key,
+            questions: String::new(),
+            status: "unreadable".into(),
+            attempts_used: 0,
+            queries: 0,
+            shortcuts: 0,
+            first_activity: None,
+            last_activity: None,
+            score: 0.0,
+            max_score: 0.0,
+        }
+    }
+}
+
+fn status_label(status: QuestionStatus) -> &'static str {
+    match status {
+        QuestionStatus::Open => "open",
+        QuestionStatus::Correct => "correct",
+        QuestionStatus::OutOfAttempts => "out of attempts",
+    }
+}
+
+/// The grades of every session in the store.
+pub async fn grade_rows(
+    store: &dyn SessionStore,
+    worlds: &WorldCache,
+) -> Result<Vec<GradeRow>, SessionError> {
+    let mut rows = vec![];
+    for key in store.list().await? {
+        rows.push(match store.get(&key).await {
+            Ok(session) => GradeRow::new(key.clone(), &session, &*worlds.get(key.as_str()).await),
+            Err(error) => GradeRow::unreadable(key, &error),
+        });
+    }
+    Ok(rows)
+}
+
+/// Write the rows out in the given format.
+pub fn format_rows(rows: &[GradeRow], format: ExportFormat) -> String {
+    match format {
+        ExportFormat::Csv => to_csv(rows),
+        ExportFormat::Json => serde_json::to_string_pretty(rows).unwrap(),
+    }
+}
+
+fn to_csv(rows: &[GradeRow]) -> String {
+    let mut csv = String::from(
+        "key,questions,status,attempts_used,queries,shortcuts,first_activity,last_activity,score,max_score\r\n",
+    );
+    let time = |when: Option<DateTime<Utc>>| when.map(|when| when.to_rfc3339()).unwrap_or_default();
+    for row in rows {
+        let fields = [
+            row.key.to_string(),
+            row.questions.clone(),
+            row.status.clone(),
+            row.attempts_used.to_string(),
+            row.queries.to_string(),
+            row.shortcuts.to_string(),
+            time(row.first_activity),
+            time(row.last_activity),
+            row.score.to_string(),
+            row.max_score.to_string(),
+        ];
+        let fields: Vec<_> = fields.iter().map(|field| csv_field(field)).collect();
+        csv.push_str(&fields.join(","));
+        csv.push_str("\r\n");
+    }
+    csv
+}
+
+/// Quote a CSV field if needed, as described in RFC 4180.
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\r', '\n']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+/// Print the grades of every session.
+pub async fn export_grades(
+    store: &dyn SessionStore,
+    worlds: &WorldCache,
+    format: ExportFormat,
+) -> Result<(), String> {
+    let rows = grade_rows(store, worlds)
+        .await
+        .map_err(|e| format!("Could not list the sessions: {e}"))?;
+    print!("{}", format_rows(&rows, format));
+    Ok(())
+}
+
+/// Write out the history of a session.
+pub fn format_trace(
+    key: &SessionKey,
+    session: &Session,
+    world: &World,
+    format: TraceFormat,
+    now: DateTime<Utc>,
+) -> String {
+    match format {
+        TraceFormat::Html => {
+            let (score, max_score) = session.total_score();
+            env()
+                .get_template("trace")
+                .unwrap()
+                .render(context!(
+                    key => key,
+                    generated_at => now,
+                    score => score,
+                    max_score => max_score,
+                    scores => session.scores(),
+                    lock_reason => session.lock_reason(),
+                    analysis => by_entry(world, session),
+                    session => session,
+                ))
+                .unwrap()
+        }
+        TraceFormat::Jsonl => session
+            .user_requests
+            .iter()
+            .map(|entry| serde_json::to_string(entry).unwrap() + "\n")
+            .collect(),
+    }
+}
+
+/// Print the history of a session.
+pub async fn export_trace(
+    store: &dyn SessionStore,
+    worlds: &WorldCache,
+    key: &SessionKey,
+    format: TraceFormat,
+) -> Result<(), String> {
+    let session = store
+        .get(key)
+        .await
+        .map_err(|e| format!("Could not read session {key}: {e}"))?;
+    let world = worlds.get(key.as_str()).await;
+    print!(
+        "{}",
+        format_trace(key, &session, &world, format, Utc::now())
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        session::{Answer, AnswerStatus, Event, Question, RequestLogEntry},
+        world::WorldData,
+    };
+
+    #[test]
+    fn test_grade_rows() {
+        let answer = || Answer::Preset { options: vec![] };
+        let mut session = Session::new(vec![
+            Question::new("What is the address of \"a, b\"?".into(), answer()),
+            Question::new("Second".into(), answer()),
+        ]);
+        let when = "2023-03-10T09:00:00Z".parse().unwrap();
+        session.user_requests.push(RequestLogEntry {
+            when,
+            what: Event::SubmitAnswer {
+                question: 0,
+                answer: "1.2.3.4".into(),
+                status: AnswerStatus::Correct,
+            },
+        });
+        session.questions[0].status = QuestionStatus::Correct;
+        let world = World::generate(&WorldData::builtin(), b"test");
+        let row = GradeRow::new(SessionKey::new("abc".into()).unwrap(), &session, &world);
+        assert_eq!(row.status, "correct | open");
+        assert_eq!(row.attempts_used, 1);
+        assert_eq!(row.queries, 0);
+        assert_eq!(row.shortcuts, 0);
+        assert_eq!(row.first_activity, Some(when));
+        assert_eq!(row.score, 10.0);
+        assert_eq!(row.max_score, 20.0);
+
+        let csv = format_rows(&[row], ExportFormat::Csv);
+        let mut lines = csv.lines();
+        assert!(lines.next().unwrap().starts_with("key,questions,"));
+        assert_eq!(
+            lines.next().unwrap(),
+            "abc,\"What is the address of \"\"a, b\"\"? | Second\",correct | open,1,0,0,\
+             2023-03-10T09:00:00+00:00,2023-03-10T09:00:00+00:00,10,20"
+        );
+        assert_eq!(lines.next(), None);
+    }
+
+    #[test]
+    fn test_trace() {
+        let mut session = Session::new(vec![Question::new(
+            "<b>Question</b>".into(),
+            Answer::Preset { options: vec![] },
+        )]);
+        for answer in ["<script>", "1.2.3.4"] {
+            session.user_requests.push(RequestLogEntry {
+                when: "2023-03-10T09:00:00Z".parse().unwrap(),
+                what: Event::SubmitAnswer {
+                    question: 0,
+                    answer: answer.into(),
+                    status: AnswerStatus::Incorrect,
+                },
+            });
+        }
+        let key = SessionKey::new("abc".into()).unwrap();
+        let world = World::generate(&WorldData::builtin(), b"test");
+        let now = Utc::now();
+
+        let jsonl = format_trace(&key, &session, &world, TraceFormat::Jsonl, now);
+        let lines: Vec<_> = jsonl.lines().collect();
+        assert_eq!(lines.len(), 2);
+        let entry: RequestLogEntry = serde_json::from_str(lines[1]).unwrap();
+        assert!(matches!(entry.what, Event::SubmitAnswer { answer, .. } if answer == "1.2.3.4"));
+
+        let html = format_trace(&key, &session, &world, TraceFormat::Html, now);
+        assert!(html.contains("&lt;b&gt;Question&lt;&#x2f;b&gt;"));
+        assert!(html.contains("&lt;script&gt;"));
+        assert!(!html.contains("<script>"));
+    }
+}
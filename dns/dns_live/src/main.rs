@@ -1,11 +1,167 @@
+mod analysis;
+mod config;
+mod export;
+mod output;
+mod password;
+mod provision;
 mod querying;
+mod questions;
 mod session;
 mod web;
+mod world;
+
+use clap::{Parser, Subcommand};
+use config::{Config, ConfigArgs};
+use export::{ExportFormat, TraceFormat};
+use provision::{Access, NewSessions};
+use session::{SessionKey, DEFAULT_ANSWERS};
+use world::{load_secret, WorldCache, WorldData};
+
+#[derive(Parser)]
+#[command(about = "DNS resolution exam task")]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+    #[command(flatten)]
+    config: ConfigArgs,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Run the web server (the default)
+    Serve,
+    /// Create a session with generated questions
+    CreateSession {
+        /// Key of the new session; random if not given
+        key: Option<SessionKey>,
+        #[command(flatten)]
+        options: NewSessions,
+    },
+    /// List all sessions with their status
+    ListSessions,
+    /// Show a session's question, expected answer and history
+    ShowSession { key: SessionKey },
+    /// Clear a session's history and restore its attempts.
+    /// Anything the student does meanwhile is lost, so only reset sessions nobody is working on
+    ResetSession {
+        key: SessionKey,
+        /// How many answers the session may submit afterwards
+        #[arg(long, default_value_t = DEFAULT_ANSWERS)]
+        answers: usize,
+    },
+    /// Create sessions with generated questions
+    BulkCreate {
+        /// How many sessions to create
+        #[arg(long)]
+        count: usize,
+        /// Prefix for the generated session keys
+        #[arg(long, default_value = "")]
+        prefix: String,
+        #[command(flatten)]
+        options: NewSessions,
+    },
+    /// Set a session's password, or remove it if neither --password nor --pin is given
+    SetPassword {
+        key: SessionKey,
+        #[command(flatten)]
+        access: Access,
+    },
+    /// Print the grades of all sessions, one line per session
+    ExportGrades {
+        #[arg(long, value_enum, default_value_t = ExportFormat::Csv)]
+        format: ExportFormat,
+    },
+    /// Print everything that happened in a session
+    ExportTrace {
+        key: SessionKey,
+        #[arg(long, value_enum, default_value_t = TraceFormat::Html)]
+        format: TraceFormat,
+    },
+    /// Read a password from standard input and print its hash, for admin_password_hash
+    HashPassword,
+}
+
+impl Command {
+    /// Whether the command builds the worlds of sessions, for which it needs the secret.
+    fn generates_worlds(&self) -> bool {
+        matches!(
+            self,
+            Command::Serve
+                | Command::CreateSession { .. }
+                | Command::ShowSession { .. }
+                | Command::BulkCreate { .. }
+                | Command::ExportGrades { .. }
+                | Command::ExportTrace { .. }
+        )
+    }
+}
 
 #[tokio::main]
 async fn main() {
-    tracing_subscriber::fmt::init();
+    let cli = Cli::parse();
+    // Logs go to stderr, so that the output of commands can be redirected to a file.
+    tracing_subscriber::fmt()
+        .with_writer(std::io::stderr)
+        .init();
     tracing::info!("Started!");
 
-    web::web_main().await;
+    if let Some(Command::HashPassword) = cli.command {
+        let mut password = String::new();
+        if let Err(e) = std::io::stdin().read_line(&mut password) {
+            eprintln!("Could not read the password: {e}");
+            std::process::exit(1);
+        }
+        println!("{}", password::hash_password(password.trim_end_matches(['\r', '\n'])));
+        return;
+    }
+
+    let config = match Config::load(cli.config) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("{e}");
+            std::process::exit(1);
+        }
+    };
+    let command = cli.command.unwrap_or(Command::Serve);
+    let data = WorldData::load(&config.root_zone, &config.domain_list);
+    // Only the commands that generate worlds need the secret.
+    let secret = if command.generates_worlds() {
+        match load_secret(&config.secret_file, config.allow_missing_secret) {
+            Ok(secret) => secret,
+            Err(e) => {
+                eprintln!("{e}");
+                std::process::exit(1);
+            }
+        }
+    } else {
+        vec![]
+    };
+    let store = match config.session_store() {
+        Ok(store) => store,
+        Err(e) => {
+            eprintln!("{e}");
+            std::process::exit(1);
+        }
+    };
+
+    let result = match command {
+        Command::Serve => web::web_main(config, store, data, secret).await,
+        Command::CreateSession { key, options } => provision::create_one(store.as_ref(), &data, &secret, key, &options).await,
+        Command::ListSessions => provision::list(store.as_ref()).await,
+        Command::ShowSession { key } => provision::show(store.as_ref(), &data, &secret, &key).await,
+        Command::ResetSession { key, answers } => provision::reset(store.as_ref(), &key, answers).await,
+        Command::BulkCreate {
+            count,
+            prefix,
+            options,
+        } => provision::bulk_create(store.as_ref(), &data, &secret, count, &prefix, &options).await,
+        Command::SetPassword { key, access } => provision::set_password(store.as_ref(), &key, &access).await,
+        Command::ExportGrades { format } => export::export_grades(store.as_ref(), &WorldCache::new(data, secret, 1), format).await,
+        Command::ExportTrace { key, format } => export::export_trace(store.as_ref(), &WorldCache::new(data, secret, 1), &key, format).await,
+        Command::HashPassword => unreachable!("handled before loading the configuration"),
+    };
+    if let Err(e) = result {
+        eprintln!("{e}");
+        std::process::exit(1);
+    }
 }
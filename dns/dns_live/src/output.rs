@@ -0,0 +1,573 @@
+//! Showing DNS messages to the student in each of the output modes.
+
+use std::{collections::BTreeMap, net::IpAddr};
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use trust_dns_client::{
+    op::{Message, MessageType, ResponseCode},
+    rr::{Name, RData, Record, RecordType},
+};
+
+use crate::{
+    session::{Answer, Event, OutputMode, ResponseResult, Session, SessionMode},
+    world::{parse_name, World},
+};
+
+use self::explain::{explain, Language};
+
+mod explain;
+
+impl OutputMode {
+    pub fn render(self, message: &Message) -> String {
+        match self {
+            OutputMode::Classic => message.to_string(),
+            OutputMode::Rust => format!("{message:#?}"),
+            OutputMode::Json => serde_json::to_string_pretty(&JsonMessage::new(message)).unwrap(),
+            OutputMode::ExplainEn => explain(message, Language::English),
+            OutputMode::ExplainRu => explain(message, Language::Russian),
+        }
+    }
+
+    /// Whether a student may switch to this mode in a session of the given kind.
+    /// Explanations give too much away to be used in an exam.
+    pub fn available_in(self, mode: SessionMode) -> bool {
+        match self {
+            OutputMode::Classic | OutputMode::Rust | OutputMode::Json => true,
+            OutputMode::ExplainEn | OutputMode::ExplainRu => mode == SessionMode::Practice,
+        }
+    }
+}
+
+/// A message in the format of RFC 8427 (Representing DNS Messages in JSON).
+/// Flags are 0 or 1, and every record has its data in presentation format
+/// under a member named after its type, such as `rdataNS`.
+#[derive(Serialize)]
+#[allow(non_snake_case)]
+struct JsonMessage {
+    ID: u16,
+    QR: u8,
+    Opcode: u8,
+    AA: u8,
+    TC: u8,
+    RD: u8,
+    RA: u8,
+    AD: u8,
+    CD: u8,
+    RCODE: u16,
+    QDCOUNT: usize,
+    ANCOUNT: usize,
+    NSCOUNT: usize,
+    ARCOUNT: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    QNAME: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    QTYPE: Option<u16>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    QTYPEname: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    QCLASS: Option<u16>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    QCLASSname: Option<String>,
+    answerRRs: Vec<JsonRecord>,
+    authorityRRs: Vec<JsonRecord>,
+    additionalRRs: Vec<JsonRecord>,
+}
+
+#[derive(Serialize)]
+#[allow(non_snake_case)]
+struct JsonRecord {
+    NAME: String,
+    TYPE: u16,
+    TYPEname: String,
+    CLASS: u16,
+    CLASSname: String,
+    TTL: u32,
+    #[serde(flatten)]
+    rdata: BTreeMap<String, String>,
+}
+
+impl JsonMessage {
+    fn new(message: &Message) -> Self {
+        let flag = |set: bool| u8::from(set);
+        let query = message.queries().first();
+        let records = |records: &[Record]| records.iter().map(JsonRecord::new).collect();
+        Self {
+            ID: message.id(),
+            QR: flag(message.message_type() == MessageType::Response),
+            Opcode: message.op_code().into(),
+            AA: flag(message.authoritative()),
+            TC: flag(message.truncated()),
+            RD: flag(message.recursion_desired()),
+            RA: flag(message.recursion_available()),
+            AD: flag(message.authentic_data()),
+            CD: flag(message.checking_disabled()),
+            RCODE: message.response_code().into(),
+            QDCOUNT: message.queries().len(),
+            ANCOUNT: message.answers().len(),
+            NSCOUNT: message.name_servers().len(),
+            ARCOUNT: message.additionals().len(),
+            QNAME: query.map(|query| query.name().to_string()),
+            QTYPE: query.map(|query| query.query_type().into()),
+            QTYPEname: query.map(|query| query.query_type().to_string()),
+            QCLASS: query.map(|query| query.query_class().into()),
+            QCLASSname: query.map(|query| query.query_class().to_string()),
+            answerRRs: records(message.answers()),
+            authorityRRs: records(message.name_servers()),
+            additionalRRs: records(message.additionals()),
+        }
+    }
+}
+
+impl JsonRecord {
+    fn new(record: &Record) -> Self {
+        let mut rdata = BTreeMap::new();
+        if let Some(data) = record.data() {
+            rdata.insert(format!("rdata{}", record.rr_type()), data.to_string());
+        }
+        Self {
+            NAME: record.name().to_string(),
+            TYPE: record.rr_type().into(),
+            TYPEname: record.rr_type().to_string(),
+            CLASS: record.dns_class().into(),
+            CLASSname: record.dns_class().to_string(),
+            TTL: record.ttl(),
+            rdata,
+        }
+    }
+}
+
+/// A message taken apart for showing it as a set of tables.
+#[derive(Serialize, Debug)]
+pub struct MessageView {
+    /// Header flags, in the order they are shown, with whether each is set.
+    flags: Vec<(&'static str, bool)>,
+    rcode: String,
+    no_error: bool,
+    question: Vec<RecordView>,
+    answers: Vec<RecordView>,
+    authority: Vec<RecordView>,
+    additional: Vec<RecordView>,
+    /// What the message means, in English and in Russian, for practice sessions.
+    explanations: Option<[String; 2]>,
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+pub struct RecordView {
+    name: String,
+    ttl: Option<u32>,
+    class: String,
+    record_type: String,
+    data: String,
+    /// The query to make next by following this record, if it points to a server.
+    follow: Option<FollowUp>,
+}
+
+/// A query to fill in the form with.
+/// Without a server address, the server's own name has to be resolved first.
+#[derive(Serialize, Debug, PartialEq)]
+pub struct FollowUp {
+    server_ip: Option<IpAddr>,
+    name: String,
+    record_type: RecordType,
+}
+
+impl MessageView {
+    pub fn new(message: &Message, explain: bool) -> Self {
+        let asked = message.queries().first();
+        // Where to send the same question next: the address of a server, for records about servers.
+        let follow_server = |server_ip: IpAddr| {
+            asked.map(|query| FollowUp {
+                server_ip: Some(server_ip),
+                name: query.name().to_string(),
+                record_type: query.query_type(),
+            })
+        };
+        // Addresses of the servers named in the message, from the glue records.
+        let glue = |host: &Name| {
+            message
+                .additionals()
+                .iter()
+                .filter(|record| record.name() == host)
+                .find_map(|record| match record.data() {
+                    Some(RData::A(addr)) => Some(IpAddr::from(*addr)),
+                    Some(RData::AAAA(addr)) => Some(IpAddr::from(*addr)),
+                    _ => None,
+                })
+        };
+        let servers = |records: &[Record]| -> Vec<RecordView> {
+            records
+                .iter()
+                .map(|record| {
+                    let follow = match record.data() {
+                        Some(RData::NS(host)) => match glue(host) {
+                            Some(addr) => follow_server(addr),
+                            None => Some(FollowUp {
+                                server_ip: None,
+                                name: host.to_string(),
+                                record_type: RecordType::A,
+                            }),
+                        },
+                        _ => None,
+                    };
+                    RecordView::new(record, follow)
+                })
+                .collect()
+        };
+        let code = message.response_code();
+        Self {
+            flags: vec![
+                ("AA", message.authoritative()),
+                ("TC", message.truncated()),
+                ("RD", message.recursion_desired()),
+                ("RA", message.recursion_available()),
+            ],
+            rcode: code.to_string(),
+            no_error: code == ResponseCode::NoError,
+            question: message
+                .queries()
+                .iter()
+                .map(|query| RecordView {
+                    name: query.name().to_string(),
+                    ttl: None,
+                    class: query.query_class().to_string(),
+                    record_type: query.query_type().to_string(),
+                    data: String::new(),
+                    follow: None,
+                })
+                .collect(),
+            answers: servers(message.answers()),
+            authority: servers(message.name_servers()),
+            additional: message
+                .additionals()
+                .iter()
+                .map(|record| {
+                    let follow = match record.data() {
+                        Some(RData::A(addr)) => follow_server(IpAddr::from(*addr)),
+                        Some(RData::AAAA(addr)) => follow_server(IpAddr::from(*addr)),
+                        _ => None,
+                    };
+                    RecordView::new(record, follow)
+                })
+                .collect(),
+            explanations: explain.then(|| {
+                [
+                    self::explain::explain(message, Language::English),
+                    self::explain::explain(message, Language::Russian),
+                ]
+            }),
+        }
+    }
+}
+
+impl RecordView {
+    fn new(record: &Record, follow: Option<FollowUp>) -> Self {
+        Self {
+            name: record.name().to_string(),
+            ttl: Some(record.ttl()),
+            class: record.dns_class().to_string(),
+            record_type: record.rr_type().to_string(),
+            data: record.data().map(ToString::to_string).unwrap_or_default(),
+            follow,
+        }
+    }
+}
+
+/// The stored responses of a session taken apart, in the order of its log.
+/// Entries that are not responses, or that were stored without the message, have none.
+/// Practice sessions also get each of them explained.
+pub fn message_views(session: &Session) -> Vec<Option<MessageView>> {
+    let explain = session.mode == SessionMode::Practice;
+    session
+        .user_requests
+        .iter()
+        .map(|entry| match &entry.what {
+            Event::Request {
+                response: ResponseResult::Ok { resp },
+                ..
+            } => resp
+                .message()
+                .map(|message| MessageView::new(&message, explain)),
+            _ => None,
+        })
+        .collect()
+}
+
+/// One query on the way to an answer.
+#[derive(Serialize, Debug)]
+pub struct PathStep {
+    server_ip: IpAddr,
+    name: String,
+    record_type: RecordType,
+    outcome: String,
+    message: MessageView,
+    /// Whether the student made this query too.
+    asked: bool,
+}
+
+/// A query that the student made for a question.
+#[derive(Serialize, Debug)]
+pub struct OwnQuery {
+    when: DateTime<Utc>,
+    server_ip: String,
+    name: String,
+    record_type: RecordType,
+    outcome: String,
+    /// Whether the query is one of the steps of the resolution path.
+    on_path: bool,
+}
+
+/// The resolution path of a question next to the queries the student made for it.
+#[derive(Serialize, Debug)]
+pub struct PathComparison {
+    reference: Vec<PathStep>,
+    own: Vec<OwnQuery>,
+}
+
+/// The resolution path of a question, if the session lets the student see it.
+pub fn revealed_path(world: &World, session: &Session, index: usize) -> Option<PathComparison> {
+    if !session.reveals_path(index) {
+        return None;
+    }
+    let explain = session.mode == SessionMode::Practice;
+    let mut reference = resolution_path(world, &session.questions.get(index)?.answer, explain)?;
+    let mut own: Vec<OwnQuery> = session
+        .user_requests
+        .iter()
+        .filter_map(|entry| match &entry.what {
+            Event::Request {
+                question,
+                request,
+                response,
+            } if *question == index => Some(OwnQuery {
+                when: entry.when,
+                server_ip: request.server_ip.clone(),
+                name: request.name.clone(),
+                record_type: request.record_type,
+                outcome: response_outcome(response),
+                on_path: false,
+            }),
+            _ => None,
+        })
+        .collect();
+    for step in &mut reference {
+        for query in &mut own {
+            if query.server_ip.parse() == Ok(step.server_ip)
+                && same_name(&query.name, &step.name)
+                && query.record_type == step.record_type
+            {
+                step.asked = true;
+                query.on_path = true;
+            }
+        }
+    }
+    Some(PathComparison { reference, own })
+}
+
+/// The queries that lead to the answer of a question, starting from a root server.
+/// Only answers that are looked up in the world have such a path.
+fn resolution_path(world: &World, answer: &Answer, explain: bool) -> Option<Vec<PathStep>> {
+    let Answer::Derived { record_type, query } = answer else {
+        return None;
+    };
+    let name = parse_name(query)?;
+    let steps = world
+        .resolution_path(&name, *record_type)
+        .into_iter()
+        .map(|step| PathStep {
+            server_ip: step.server,
+            name: step.name.to_string(),
+            record_type: step.query_type,
+            outcome: outcome(&step.response),
+            message: MessageView::new(&step.response, explain),
+            asked: false,
+        })
+        .collect();
+    Some(steps)
+}
+
+/// Whether two names typed in different ways are the same, ignoring case and the final dot.
+fn same_name(a: &str, b: &str) -> bool {
+    a.trim()
+        .trim_end_matches('.')
+        .eq_ignore_ascii_case(b.trim().trim_end_matches('.'))
+}
+
+/// What a response amounts to, in a few words.
+pub fn outcome(message: &Message) -> String {
+    let code = message.response_code();
+    if code != ResponseCode::NoError {
+        return code.to_string();
+    }
+    if !message.answers().is_empty() {
+        let data: Vec<String> = message
+            .answers()
+            .iter()
+            .filter_map(|record| record.data().map(ToString::to_string))
+            .collect();
+        return format!("answer: {}", data.join(", "));
+    }
+    if !message.authoritative() {
+        let zone = message
+            .name_servers()
+            .iter()
+            .find(|record| record.rr_type() == RecordType::NS);
+        if let Some(zone) = zone {
+            return format!("referral to {}", zone.name());
+        }
+    }
+    "no records".to_owned()
+}
+
+/// What a stored response amounts to, in a few words.
+fn response_outcome(response: &ResponseResult) -> String {
+    match response {
+        ResponseResult::Ok { resp } => match resp.message() {
+            Some(message) => outcome(&message),
+            None => "response".to_owned(),
+        },
+        ResponseResult::QueryError { err } => err.clone(),
+        ResponseResult::ForbiddenRecursion { .. } => "not allowed".to_owned(),
+        ResponseResult::InvalidRequestIpAddr { .. } => "invalid address".to_owned(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::net::Ipv4Addr;
+
+    use serde_json::{json, Value};
+    use trust_dns_client::op::Query;
+
+    use super::*;
+
+    #[test]
+    fn test_message_view() {
+        let name = |name: &str| Name::from_ascii(name).unwrap();
+        let mut message = Message::new();
+        message
+            .add_query(Query::query(name("www.example.com."), RecordType::A))
+            .add_name_servers([
+                Record::from_rdata(name("com."), 300, RData::NS(name("a.gtld.net."))),
+                Record::from_rdata(name("com."), 300, RData::NS(name("b.gtld.net."))),
+            ])
+            .add_additional(Record::from_rdata(
+                name("a.gtld.net."),
+                300,
+                RData::A(Ipv4Addr::new(1, 2, 3, 4)),
+            ));
+        let view = MessageView::new(&message, false);
+        assert_eq!(view.flags[0], ("AA", false));
+        assert!(view.no_error);
+        assert_eq!(view.question[0].name, "www.example.com.");
+
+        let glued = Some(FollowUp {
+            server_ip: Some("1.2.3.4".parse().unwrap()),
+            name: "www.example.com.".to_string(),
+            record_type: RecordType::A,
+        });
+        assert_eq!(view.authority[0].follow, glued);
+        assert_eq!(view.additional[0].follow, glued);
+        assert_eq!(
+            view.authority[1].follow,
+            Some(FollowUp {
+                server_ip: None,
+                name: "b.gtld.net.".to_string(),
+                record_type: RecordType::A,
+            })
+        );
+    }
+
+    #[test]
+    fn test_revealed_path() {
+        use crate::session::{Question, Request, RequestLogEntry, Response};
+        use crate::world::WorldData;
+
+        let world = World::generate(&WorldData::builtin(), b"test");
+        let root = world.root_server_addresses()[0];
+        let answer = Answer::Derived {
+            record_type: RecordType::A,
+            query: "www.wikipedia.org".to_string(),
+        };
+        let mut session = Session::new(vec![Question::new("?".to_string(), answer)]);
+        for name in ["WWW.wikipedia.org", "wikipedia.org"] {
+            let name = parse_name(name).unwrap();
+            let message = world.query(root.into(), &name, RecordType::A).unwrap();
+            session.user_requests.push(RequestLogEntry {
+                when: Utc::now(),
+                what: Event::Request {
+                    question: 0,
+                    request: Request {
+                        server_ip: root.to_string(),
+                        name: name.to_string(),
+                        record_type: RecordType::A,
+                    },
+                    response: ResponseResult::Ok {
+                        resp: Response::new(&message, OutputMode::Classic),
+                    },
+                },
+            });
+        }
+        assert!(revealed_path(&world, &session, 0).is_none());
+
+        session.can_answer = false;
+        let path = revealed_path(&world, &session, 0).unwrap();
+        assert_eq!(path.reference.len(), 3);
+        assert!(path.reference[0].asked);
+        assert!(!path.reference[1].asked);
+        assert_eq!(path.reference[0].outcome, "referral to org.");
+        assert!(path.reference[2].outcome.starts_with("answer: "));
+        assert!(path.reference[0].message.explanations.is_none());
+        assert!(path.own[0].on_path);
+        assert!(!path.own[1].on_path);
+    }
+
+    #[test]
+    fn test_json() {
+        let name = Name::from_ascii("example.com.").unwrap();
+        let mut message = Message::new();
+        message
+            .set_id(7)
+            .set_message_type(MessageType::Response)
+            .set_authoritative(true)
+            .add_query(Query::query(name.clone(), RecordType::A))
+            .add_answer(Record::from_rdata(
+                name,
+                300,
+                RData::A(Ipv4Addr::new(1, 2, 3, 4)),
+            ));
+        let json: Value = serde_json::from_str(&OutputMode::Json.render(&message)).unwrap();
+        assert_eq!(json["ID"], 7);
+        assert_eq!(json["QR"], 1);
+        assert_eq!(json["AA"], 1);
+        assert_eq!(json["RD"], 0);
+        assert_eq!(json["QDCOUNT"], 1);
+        assert_eq!(json["QNAME"], "example.com.");
+        assert_eq!(json["QTYPEname"], "A");
+        assert_eq!(
+            json["answerRRs"],
+            json!([{
+                "NAME": "example.com.",
+                "TYPE": 1,
+                "TYPEname": "A",
+                "CLASS": 1,
+                "CLASSname": "IN",
+                "TTL": 300,
+                "rdataA": "1.2.3.4",
+            }])
+        );
+        assert_eq!(json["authorityRRs"], json!([]));
+    }
+
+    #[test]
+    fn test_modes_available_in_exams() {
+        for mode in [OutputMode::Classic, OutputMode::Rust, OutputMode::Json] {
+            assert!(mode.available_in(SessionMode::Exam));
+            assert!(mode.available_in(SessionMode::Practice));
+        }
+        for mode in [OutputMode::ExplainEn, OutputMode::ExplainRu] {
+            assert!(!mode.available_in(SessionMode::Exam));
+            assert!(mode.available_in(SessionMode::Practice));
+        }
+    }
+}
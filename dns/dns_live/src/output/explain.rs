@@ -0,0 +1,295 @@
+//! Explaining a response in plain words, for students who are still learning to read them.
+//!
+//! The explanation only retells what is in the message: what kind of response it is,
+//! where to go next and which records say so. It never looks anything up by itself.
+
+use std::fmt::Write;
+
+use trust_dns_client::{
+    op::{Message, ResponseCode},
+    rr::{Name, RData, Record, RecordType},
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Language {
+    English,
+    Russian,
+}
+
+use Language::{English, Russian};
+
+/// What a response means, one sentence per line.
+pub fn explain(message: &Message, language: Language) -> String {
+    let mut text = Explanation {
+        language,
+        text: String::new(),
+    };
+    let Some(query) = message.queries().first() else {
+        text.say(
+            "The response does not say what was asked.",
+            "В ответе не указано, о чем был запрос.",
+        );
+        return text.text;
+    };
+    let name = query.name();
+    let asked = query.query_type();
+    text.say(
+        &format!("You asked for the {asked} records of {name} (see the question section)."),
+        &format!("Вы запросили записи {asked} для имени {name} (раздел вопроса)."),
+    );
+    if !message.recursion_available() {
+        text.say(
+            "The server does not do recursion (RA is not set): it only tells what it knows itself, and you follow the referrals.",
+            "Сервер не выполняет рекурсивных запросов (флаг RA не установлен): он сообщает только то, что знает сам, а по ссылкам на другие серверы нужно идти самостоятельно.",
+        );
+    }
+
+    let soa = message
+        .name_servers()
+        .iter()
+        .find(|record| record.rr_type() == RecordType::SOA);
+    match message.response_code() {
+        ResponseCode::NoError => {}
+        ResponseCode::NXDomain => {
+            text.say(
+                &format!("The name {name} does not exist (NXDOMAIN)."),
+                &format!("Имени {name} не существует (NXDOMAIN)."),
+            );
+            if let Some(soa) = soa {
+                text.soa(soa);
+            }
+            return text.text;
+        }
+        code => {
+            text.say(
+                &format!("The server returned an error: {code}. There is nothing else in the response to rely on."),
+                &format!("Сервер вернул ошибку: {code}. Больше в ответе нет ничего, на что можно опереться."),
+            );
+            return text.text;
+        }
+    }
+
+    if !message.answers().is_empty() {
+        if message.authoritative() {
+            text.say(
+                "This server is authoritative for the name (AA is set), so its answer is final.",
+                "Этот сервер авторитетен для имени (флаг AA установлен), поэтому его ответ окончательный.",
+            );
+        } else {
+            text.say(
+                "The answer is not authoritative (AA is not set): the server is retelling what it heard from others.",
+                "Ответ не авторитетный (флаг AA не установлен): сервер пересказывает то, что узнал от других.",
+            );
+        }
+        text.say("The answer section contains:", "В разделе ответа:");
+        for record in message.answers() {
+            text.answer(record);
+        }
+        let has_asked = message
+            .answers()
+            .iter()
+            .any(|record| record.rr_type() == asked);
+        let alias = message
+            .answers()
+            .iter()
+            .find_map(|record| match record.data() {
+                Some(RData::CNAME(target)) => Some(target),
+                _ => None,
+            });
+        if let (false, Some(target)) = (has_asked, alias) {
+            text.say(
+                &format!("There are no {asked} records here yet: look up {target} to continue."),
+                &format!(
+                    "Записей {asked} здесь еще нет: чтобы продолжить, запросите {target} отдельно."
+                ),
+            );
+        }
+        return text.text;
+    }
+
+    let servers: Vec<&Name> = message
+        .name_servers()
+        .iter()
+        .filter_map(|record| match record.data() {
+            Some(RData::NS(host)) => Some(host),
+            _ => None,
+        })
+        .collect();
+    if !servers.is_empty() && !message.authoritative() {
+        let zone = message
+            .name_servers()
+            .iter()
+            .find(|record| record.rr_type() == RecordType::NS)
+            .map(Record::name)
+            .unwrap_or(name);
+        text.say(
+            &format!("This server is not authoritative for the name; it refers you to the servers of the zone {zone}, listed as NS records in the authority section:"),
+            &format!("Этот сервер не авторитетен для имени; он направляет вас к серверам зоны {zone}, которые перечислены в записях NS в разделе полномочий:"),
+        );
+        let count = servers.len();
+        let mut missing = 0;
+        for host in servers {
+            let glue: Vec<String> = message
+                .additionals()
+                .iter()
+                .filter(|record| record.name() == host)
+                .filter_map(|record| match record.data() {
+                    Some(RData::A(addr)) => Some(addr.to_string()),
+                    Some(RData::AAAA(addr)) => Some(addr.to_string()),
+                    _ => None,
+                })
+                .collect();
+            if glue.is_empty() {
+                missing += 1;
+                text.line(&format!("  {host}"));
+            } else {
+                text.line(&format!("  {host} ({})", glue.join(", ")));
+            }
+        }
+        if missing < count {
+            text.say(
+                "Glue addresses of these servers are in the additional section: ask one of them the same question.",
+                "Адреса этих серверов (glue-записи) есть в разделе дополнительной информации: задайте тот же вопрос одному из них.",
+            );
+        }
+        if missing > 0 {
+            text.say(
+                "For the servers without an address in the response, find their address first, starting from the root.",
+                "Для серверов, адреса которых в ответе нет, сначала нужно найти адрес, начиная с корневых серверов.",
+            );
+        }
+        return text.text;
+    }
+
+    text.say(
+        &format!(
+            "The name {name} exists, but has no {asked} records (an empty answer with NOERROR)."
+        ),
+        &format!(
+            "Имя {name} существует, но записей {asked} у него нет (пустой ответ с кодом NOERROR)."
+        ),
+    );
+    if let Some(soa) = soa {
+        text.soa(soa);
+    }
+    text.text
+}
+
+struct Explanation {
+    language: Language,
+    text: String,
+}
+
+impl Explanation {
+    fn say(&mut self, english: &str, russian: &str) {
+        self.line(match self.language {
+            English => english,
+            Russian => russian,
+        });
+    }
+
+    fn line(&mut self, line: &str) {
+        self.text.push_str(line);
+        self.text.push('\n');
+    }
+
+    fn soa(&mut self, soa: &Record) {
+        let zone = soa.name();
+        self.say(
+            &format!("The SOA record of the zone {zone} in the authority section shows who decided this."),
+            &format!("Так решено в зоне {zone}: ее запись SOA есть в разделе полномочий."),
+        );
+    }
+
+    fn answer(&mut self, record: &Record) {
+        let name = record.name();
+        let mut line = String::from("  ");
+        match record.data() {
+            Some(RData::A(addr)) => write!(line, "{name} -> {addr} (A)"),
+            Some(RData::AAAA(addr)) => write!(line, "{name} -> {addr} (AAAA)"),
+            Some(RData::CNAME(target)) => match self.language {
+                English => write!(line, "{name} is an alias of {target} (CNAME)"),
+                Russian => write!(line, "{name} -- псевдоним имени {target} (CNAME)"),
+            },
+            Some(RData::MX(mx)) => match self.language {
+                English => write!(
+                    line,
+                    "mail for {name} goes to {} (MX, preference {})",
+                    mx.exchange(),
+                    mx.preference()
+                ),
+                Russian => write!(
+                    line,
+                    "почту для {name} принимает {} (MX, приоритет {})",
+                    mx.exchange(),
+                    mx.preference()
+                ),
+            },
+            Some(data) => write!(line, "{name} {} {data}", record.rr_type()),
+            None => write!(line, "{name} {}", record.rr_type()),
+        }
+        .unwrap();
+        self.line(&line);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::net::Ipv4Addr;
+
+    use trust_dns_client::op::Query;
+
+    use super::*;
+
+    fn name(name: &str) -> Name {
+        Name::from_ascii(name).unwrap()
+    }
+
+    #[test]
+    fn test_explain_referral() {
+        let mut message = Message::new();
+        message
+            .add_query(Query::query(name("www.example.com."), RecordType::A))
+            .add_name_servers([
+                Record::from_rdata(name("com."), 300, RData::NS(name("a.gtld.net."))),
+                Record::from_rdata(name("com."), 300, RData::NS(name("b.gtld.net."))),
+            ])
+            .add_additional(Record::from_rdata(
+                name("a.gtld.net."),
+                300,
+                RData::A(Ipv4Addr::new(1, 2, 3, 4)),
+            ));
+        let english = explain(&message, English);
+        assert!(english.contains("not authoritative for the name"));
+        assert!(english.contains("zone com."));
+        assert!(english.contains("  a.gtld.net. (1.2.3.4)\n  b.gtld.net.\n"));
+        assert!(english.contains("Glue addresses"));
+        assert!(english.contains("find their address first"));
+        let russian = explain(&message, Russian);
+        assert!(russian.contains("не авторитетен"));
+        assert!(russian.contains("  a.gtld.net. (1.2.3.4)\n"));
+    }
+
+    #[test]
+    fn test_explain_answer() {
+        let mut message = Message::new();
+        message
+            .set_authoritative(true)
+            .set_recursion_available(true)
+            .add_query(Query::query(name("www.example.com."), RecordType::A))
+            .add_answer(Record::from_rdata(
+                name("www.example.com."),
+                300,
+                RData::CNAME(name("example.com.")),
+            ));
+        let english = explain(&message, English);
+        assert!(!english.contains("recursion"));
+        assert!(english.contains("authoritative for the name (AA is set)"));
+        assert!(english.contains("www.example.com. is an alias of example.com."));
+        assert!(english.contains("look up example.com. to continue"));
+
+        message.set_response_code(ResponseCode::NXDomain);
+        message.take_answers();
+        assert!(explain(&message, English).contains("does not exist"));
+    }
+}
@@ -0,0 +1,69 @@
+//! Hashing the passwords of sessions and of the teacher.
+//!
+//! Passwords are stored as Argon2 hashes in the PHC string format (`$argon2id$v=19$...`),
+//! which carries its own salt and parameters.
+
+use argon2::{
+    password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
+    Argon2,
+};
+use rand::Rng;
+
+/// Length of generated PINs.
+const PIN_LENGTH: usize = 6;
+
+/// Hash a password for storing.
+pub fn hash_password(password: &str) -> String {
+    let salt = SaltString::generate(rand::thread_rng());
+    Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .expect("Argon2 can hash any password with the default parameters")
+        .to_string()
+}
+
+/// Whether the password matches a stored hash.
+/// A hash that cannot be parsed matches nothing.
+pub fn verify_password(hash: &str, password: &str) -> bool {
+    let Ok(hash) = PasswordHash::new(hash) else {
+        tracing::error!("Invalid password hash: {hash:?}");
+        return false;
+    };
+    Argon2::default()
+        .verify_password(password.as_bytes(), &hash)
+        .is_ok()
+}
+
+/// Whether a string looks like something that [`hash_password`] produced.
+pub fn is_valid_hash(hash: &str) -> bool {
+    PasswordHash::new(hash).is_ok()
+}
+
+/// A random numeric PIN, easy to hand out on paper.
+pub fn random_pin() -> String {
+    let mut rng = rand::thread_rng();
+    (0..PIN_LENGTH)
+        .map(|_| char::from(b'0' + rng.gen_range(0..10)))
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_passwords() {
+        let hash = hash_password("123456");
+        assert!(hash.starts_with("$argon2id$"));
+        assert!(is_valid_hash(&hash));
+        assert!(verify_password(&hash, "123456"));
+        assert!(!verify_password(&hash, "123457"));
+        assert!(!verify_password(&hash, ""));
+        // The same password gets a different salt every time.
+        assert_ne!(hash, hash_password("123456"));
+        assert!(!verify_password("123456", "123456"));
+
+        let pin = random_pin();
+        assert_eq!(pin.len(), PIN_LENGTH);
+        assert!(pin.chars().all(|c| c.is_ascii_digit()));
+    }
+}
@@ -0,0 +1,408 @@
+//! Command-line tools for preparing sessions before an exam.
+//!
+//! They fail with a message to print, so that the process can exit with an error.
+
+use chrono::{DateTime, Utc};
+use clap::Args;
+use rand::{distributions::Alphanumeric, seq::SliceRandom, Rng};
+
+use crate::{
+    password::{hash_password, random_pin},
+    questions::QuestionTemplate,
+    session::{
+        Answer, CreateError, Event, KeyError, Question, QuestionStatus, ResponseResult, Scoring,
+        Session, SessionKey, SessionMode, SessionStore,
+    },
+    world::{parse_name, session_seed, World, WorldData},
+};
+
+/// Length of the random part of generated session keys.
+const KEY_LENGTH: usize = 8;
+
+/// When the created sessions may be worked on.
+#[derive(Args, Debug)]
+struct Schedule {
+    /// Do not accept any actions before this moment, e.g. 2023-03-10T09:00:00+03:00
+    #[arg(long)]
+    opens_at: Option<DateTime<Utc>>,
+    /// Minutes the student has from when they first open the session
+    #[arg(long)]
+    duration: Option<u32>,
+    /// Do not accept any actions from this moment on, e.g. 2023-03-10T11:00:00+03:00
+    #[arg(long)]
+    closes_at: Option<DateTime<Utc>>,
+}
+
+impl Schedule {
+    fn apply(&self, session: &mut Session) {
+        session.opens_at = self.opens_at;
+        session.duration = self.duration;
+        session.closes_at = self.closes_at;
+    }
+}
+
+/// Which questions the created sessions ask.
+#[derive(Args, Debug)]
+struct Questions {
+    /// Kind of question to ask; repeat to ask several questions, in this order.
+    /// If not given, the kinds are chosen at random
+    #[arg(long = "template", value_enum)]
+    templates: Vec<QuestionTemplate>,
+    /// How many questions of random kinds to ask, if no --template is given
+    #[arg(long, default_value_t = 1)]
+    questions: usize,
+    /// Points for a correct answer to each question
+    #[arg(long, default_value_t = Scoring::default().points)]
+    points: f64,
+    /// Points taken off for every incorrect answer
+    #[arg(long, default_value_t = Scoring::default().wrong_answer_penalty)]
+    wrong_answer_penalty: f64,
+    /// How many queries should be enough to answer each question
+    #[arg(long)]
+    query_budget: Option<usize>,
+    /// Points taken off for every query beyond the budget
+    #[arg(long, default_value_t = Scoring::default().over_budget_penalty)]
+    over_budget_penalty: f64,
+    /// Points added for every query of the budget that was not made
+    #[arg(long, default_value_t = Scoring::default().under_budget_bonus)]
+    under_budget_bonus: f64,
+}
+
+impl Questions {
+    /// Generate the questions for the session with this key.
+    /// Returns `None` if the world has no domains to ask about.
+    fn generate(&self, data: &WorldData, secret: &[u8], key: &SessionKey) -> Option<Vec<Question>> {
+        let world = World::generate(data, &session_seed(secret, key.as_str()));
+        let mut rng = world.rng_for("question");
+        let templates = if self.templates.is_empty() {
+            (0..self.questions)
+                .map(|_| *QuestionTemplate::ALL.choose(&mut rng).unwrap())
+                .collect()
+        } else {
+            self.templates.clone()
+        };
+        let scoring = Scoring {
+            points: self.points,
+            wrong_answer_penalty: self.wrong_answer_penalty,
+            query_budget: self.query_budget,
+            over_budget_penalty: self.over_budget_penalty,
+            under_budget_bonus: self.under_budget_bonus,
+        };
+        templates
+            .into_iter()
+            .map(|template| {
+                let mut question = template.generate(&world, &mut rng)?;
+                question.scoring = scoring.clone();
+                Some(question)
+            })
+            .collect()
+    }
+}
+
+/// Who may open the sessions.
+#[derive(Args, Debug)]
+pub struct Access {
+    /// Password that the student has to enter to open the session
+    #[arg(long)]
+    password: Option<String>,
+    /// Protect every session with a random PIN of its own, printed after its key
+    #[arg(long, conflicts_with = "password")]
+    pin: bool,
+}
+
+impl Access {
+    /// Set the password of a session, or remove it if none is given.
+    /// Returns the PIN, if one was made up.
+    fn apply(&self, session: &mut Session) -> Option<String> {
+        let password = if self.pin {
+            Some(random_pin())
+        } else {
+            self.password.clone()
+        };
+        session.password_hash = password.as_deref().map(hash_password);
+        password.filter(|_| self.pin)
+    }
+}
+
+/// Everything about the sessions to create, other than their keys.
+#[derive(Args, Debug)]
+pub struct NewSessions {
+    #[command(flatten)]
+    questions: Questions,
+    #[command(flatten)]
+    schedule: Schedule,
+    #[command(flatten)]
+    access: Access,
+    /// Make practice sessions, with unlimited attempts, hints and explained responses
+    #[arg(long)]
+    practice: bool,
+}
+
+impl NewSessions {
+    /// A new session with this key, along with the line to print about it once it is created:
+    /// its key, its PIN if it has one, and its questions.
+    /// Returns `None` if the world has no domains to ask about.
+    fn make(&self, data: &WorldData, secret: &[u8], key: &SessionKey) -> Option<(Session, String)> {
+        let questions = self.questions.generate(data, secret, key)?;
+        let mut session = Session::new(questions);
+        self.schedule.apply(&mut session);
+        if self.practice {
+            session.mode = SessionMode::Practice;
+        }
+        let pin = self.access.apply(&mut session);
+        let mut fields = vec![key.to_string()];
+        fields.extend(pin);
+        fields.extend(session.questions.iter().map(|q| q.text.clone()));
+        Some((session, fields.join("\t")))
+    }
+}
+
+/// Create a single session with generated questions, printing its key and questions.
+/// If no key is given, a random one is made up.
+pub async fn create_one(
+    store: &dyn SessionStore,
+    data: &WorldData,
+    secret: &[u8],
+    key: Option<SessionKey>,
+    options: &NewSessions,
+) -> Result<(), String> {
+    let key = key.unwrap_or_else(|| random_key("").expect("random keys are valid"));
+    let (session, line) = options
+        .make(data, secret, &key)
+        .ok_or("The world has no domains to ask about")?;
+    store
+        .create_session(&key, session)
+        .await
+        .map_err(|e| format!("Could not create session {key}: {e}"))?;
+    println!("{line}");
+    Ok(())
+}
+
+/// Print a line for every session: key, whether it is open, questions answered correctly,
+/// score, number of events, and the first question.
+pub async fn list(store: &dyn SessionStore) -> Result<(), String> {
+    let keys = store
+        .list()
+        .await
+        .map_err(|e| format!("Could not list the sessions: {e}"))?;
+    for key in keys {
+        match store.get(&key).await {
+            Ok(session) => println!(
+                "{key}\t{}\t{}/{}\t{}\t{}\t{}",
+                if session.can_answer { "open" } else { "locked" },
+                session
+                    .questions
+                    .iter()
+                    .filter(|q| q.status == QuestionStatus::Correct)
+                    .count(),
+                session.questions.len(),
+                format_total(&session),
+                session.user_requests.len(),
+                session.questions.first().map_or("", |q| q.text.as_str())
+            ),
+            Err(e) => println!("{key}\tunreadable: {e}"),
+        }
+    }
+    Ok(())
+}
+
+/// Print everything about a session, including the expected answer in its world.
+pub async fn show(
+    store: &dyn SessionStore,
+    data: &WorldData,
+    secret: &[u8],
+    key: &SessionKey,
+) -> Result<(), String> {
+    let session = read(store, key).await?;
+    println!("Key: {key}");
+    println!(
+        "Status: {}",
+        if session.can_answer { "open" } else { "locked" }
+    );
+    println!("Mode: {:?}", session.mode);
+    println!(
+        "Password: {}",
+        if session.password_hash.is_some() {
+            "set"
+        } else {
+            "none"
+        }
+    );
+    let world = World::generate(data, &session_seed(secret, key.as_str()));
+    for (i, (question, score)) in session.questions.iter().zip(session.scores()).enumerate() {
+        println!("Question {}: {}", i + 1, question.text);
+        match &question.answer {
+            Answer::Derived { record_type, query } => {
+                let expected = parse_name(query)
+                    .and_then(|name| world.resolve(&name, *record_type))
+                    .unwrap_or_default();
+                println!("    Expected answer: IN {record_type} {query}, currently:");
+                for record in expected.iter().filter_map(|r| r.data()) {
+                    println!("        {record}");
+                }
+            }
+            Answer::Preset { options } => println!("    Expected answer: one of {options:?}"),
+        }
+        println!(
+            "    Attempts left: {} ({:?})",
+            question.answers_remaining, question.status
+        );
+        let scoring = &question.scoring;
+        print!(
+            "    Scoring: {} points, -{} per incorrect answer",
+            scoring.points, scoring.wrong_answer_penalty
+        );
+        match scoring.query_budget {
+            Some(budget) => println!(
+                ", budget of {budget} queries: -{} per query over, +{} per query under",
+                scoring.over_budget_penalty, scoring.under_budget_bonus
+            ),
+            None => println!(),
+        }
+        println!(
+            "    Score: {}/{} ({} queries, {} incorrect answers)",
+            score.points, score.max_points, score.queries, score.wrong_answers
+        );
+    }
+    println!("Total score: {}", format_total(&session));
+    if let Some(opens_at) = session.opens_at {
+        println!("Opens at: {opens_at}");
+    }
+    if let Some(duration) = session.duration {
+        match session.started_at {
+            Some(started_at) => println!("Duration: {duration} minutes, started at {started_at}"),
+            None => println!("Duration: {duration} minutes, not started yet"),
+        }
+    }
+    if let Some(closes_at) = session.closes_at {
+        println!("Closes at: {closes_at}");
+    }
+    println!("Output mode: {:?}", session.current_output_mode);
+    println!("History:");
+    for entry in &session.user_requests {
+        let what = match &entry.what {
+            Event::Request {
+                question,
+                request,
+                response,
+            } => {
+                let result = match response {
+                    ResponseResult::Ok { .. } => "ok".to_owned(),
+                    ResponseResult::QueryError { err } => format!("error {err}"),
+                    ResponseResult::ForbiddenRecursion { addr } => format!("recursive {addr}"),
+                    ResponseResult::InvalidRequestIpAddr { addr } => format!("bad address {addr}"),
+                };
+                format!(
+                    "query for question {}: IN {} {} @{}: {result}",
+                    question + 1,
+                    request.record_type,
+                    request.name,
+                    request.server_ip
+                )
+            }
+            Event::SwitchOutputMode { new_mode } => format!("output mode {new_mode:?}"),
+            Event::SubmitAnswer {
+                question,
+                answer,
+                status,
+            } => format!(
+                "answer to question {}: {answer:?}: {status:?}",
+                question + 1
+            ),
+            Event::Locked { reason } => format!("locked: {reason:?}"),
+        };
+        println!("    {} {what}", entry.when);
+    }
+    Ok(())
+}
+
+/// Clear the history of a session and give it back its attempts, keeping the questions.
+///
+/// The running server's session locks do not reach this process,
+/// so whatever the student does while the session is being reset is lost.
+/// Reset sessions that nobody is working on.
+pub async fn reset(
+    store: &dyn SessionStore,
+    key: &SessionKey,
+    answers: usize,
+) -> Result<(), String> {
+    let mut session = read(store, key).await?;
+    session.reset(answers);
+    store
+        .update_session(key, session)
+        .await
+        .map_err(|e| format!("Could not save session {key}: {e}"))
+}
+
+/// Set or remove the password of a session, printing the PIN if one was made up.
+///
+/// The history of the session is kept as it is stored, including anything added meanwhile,
+/// but other changes the running server makes to the session at the same moment may be lost.
+pub async fn set_password(
+    store: &dyn SessionStore,
+    key: &SessionKey,
+    access: &Access,
+) -> Result<(), String> {
+    let mut session = read(store, key).await?;
+    let pin = access.apply(&mut session);
+    store
+        .append(key, session, vec![])
+        .await
+        .map_err(|e| format!("Could not save session {key}: {e}"))?;
+    if let Some(pin) = pin {
+        println!("{key}\t{pin}");
+    }
+    Ok(())
+}
+
+/// Create `count` sessions with generated questions, printing their keys and questions.
+/// If no template is given, each session gets randomly chosen kinds of questions.
+pub async fn bulk_create(
+    store: &dyn SessionStore,
+    data: &WorldData,
+    secret: &[u8],
+    count: usize,
+    prefix: &str,
+    options: &NewSessions,
+) -> Result<(), String> {
+    let mut created = 0;
+    while created < count {
+        let key = random_key(prefix).map_err(|e| format!("Invalid key prefix {prefix:?}: {e}"))?;
+        let (session, line) = options
+            .make(data, secret, &key)
+            .ok_or("The world has no domains to ask about")?;
+        match store.create_session(&key, session).await {
+            Ok(()) => {}
+            // The key is taken; try another one.
+            Err(CreateError::AlreadyExists) => continue,
+            Err(e) => return Err(format!("Could not create session {key}: {e}")),
+        }
+        println!("{line}");
+        created += 1;
+    }
+    Ok(())
+}
+
+/// Read a session, failing with a message if it cannot be read.
+async fn read(store: &dyn SessionStore, key: &SessionKey) -> Result<Session, String> {
+    store
+        .get(key)
+        .await
+        .map_err(|e| format!("Could not read session {key}: {e}"))
+}
+
+/// The score of a whole session, out of the most it could be.
+fn format_total(session: &Session) -> String {
+    let (points, max) = session.total_score();
+    format!("{points}/{max}")
+}
+
+/// A session key that is hard to guess: the prefix, followed by random characters.
+fn random_key(prefix: &str) -> Result<SessionKey, KeyError> {
+    let suffix: String = rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(KEY_LENGTH)
+        .map(|c| char::from(c).to_ascii_lowercase())
+        .collect();
+    SessionKey::new(format!("{prefix}{suffix}"))
+}
@@ -1,184 +1,161 @@
-use std::net::SocketAddr;
-use std::str::FromStr;
-use trust_dns_client::client::{Client, ClientHandle, SyncClient};
-use trust_dns_client::op::{DnsResponse, ResponseCode};
-use trust_dns_client::rr::{DNSClass, Name, RecordType};
-use trust_dns_client::udp::UdpClientConnection;
-use trust_dns_resolver::config::{ResolverConfig, ResolverOpts};
-use trust_dns_resolver::TokioAsyncResolver;
+use std::fmt::Display;
+use std::net::IpAddr;
+
+use trust_dns_client::op::Message;
+use trust_dns_client::rr::{RData, RecordType};
 
 use crate::session::Answer;
+use crate::world::{parse_name, World};
 
 #[derive(Debug)]
 pub enum QueryError {
+    /// There is no server at this address
     Timeout,
-    Unexpected(String),
+    /// The requested name is not a valid domain name
+    InvalidName(String),
 }
 
-pub async fn perform_query(
-    server: SocketAddr,
-    name: &str,
-    query_type: RecordType,
-) -> Result<DnsResponse, QueryError> {
-    let conn = UdpClientConnection::new(server).unwrap(); // infallible
-    let client = SyncClient::new(conn);
-    let (mut client, bg) = client
-        .new_future()
-        .await
-        .map_err(|e| QueryError::Unexpected(format!("{e:?}")))?;
-    let bg_task = tokio::spawn(bg);
-    let name = Name::from_str(name).unwrap();
-
-    let response: DnsResponse = match client.query(name, DNSClass::IN, query_type).await {
-        Ok(resp) => resp,
-        Err(error) => {
-            let response = match error.kind() {
-                // trust_dns_client::error::ClientErrorKind::Message(_) => todo!(),
-                // trust_dns_client::error::ClientErrorKind::Msg(_) => todo!(),
-                // trust_dns_client::error::ClientErrorKind::DnsSec(_) => todo!(),
-                // trust_dns_client::error::ClientErrorKind::Io(_) => todo!(),
-                // trust_dns_client::error::ClientErrorKind::Proto(_) => todo!(),
-                // trust_dns_client::error::ClientErrorKind::SendError(_) => todo!(),
-                trust_dns_client::error::ClientErrorKind::Timeout => Err(QueryError::Timeout),
-                _ => Err(QueryError::Unexpected(format!("{error:?}"))),
-            };
-            bg_task.abort();
-            return response;
+impl Display for QueryError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            QueryError::Timeout => write!(f, "Timeout"),
+            QueryError::InvalidName(name) => write!(f, "InvalidName({name:?})"),
         }
-    };
-
-    // Messages are the packets sent between client and server in DNS.
-    //  there are many fields to a Message, DnsResponse can be dereferenced into
-    //  a Message. It's beyond the scope of these examples
-    //  to explain all the details of a Message. See trust_dns_client::op::message::Message for more details.
-    //  generally we will be interested in the Message::answers
-    bg_task.abort();
-    Ok(response)
+    }
 }
 
-/// Check whether a NS server is recursive
-/// by querying it for several domains across multiple zones,
-/// and seeing if it gives answers for all of them.
-pub async fn is_recursive_server(server: SocketAddr) -> bool {
-    let names = vec![
-        "example.com",
-        "example.net",
-        "example.org",
-        "iana.org",
-        "cctld.ru",
-        "bit.ly",
-        "nih.gov",
-        "github.io",
-    ];
-    let successes_needed = 4; // this or more responses with answer sections are needed
-
-    let mut tasks = vec![];
+/// Send a query to one of the servers of the simulated world.
+pub fn perform_query(
+    world: &World,
+    server: IpAddr,
+    name: &str,
+    query_type: RecordType,
+) -> Result<Message, QueryError> {
+    let name = parse_name(name).ok_or_else(|| QueryError::InvalidName(name.to_owned()))?;
+    world.query(server, &name, query_type)
+}
 
-    for name in names {
-        tasks.push(tokio::spawn(perform_query(server, name, RecordType::A)));
-    }
-    let mut successes = 0;
-    for task in tasks {
-        let resp = task.await; // Result representing joining the task
-        if resp.is_err() {
-            continue;
-        }
-        let resp = resp.unwrap(); // Result representing querying.
-        if resp.is_err() {
-            continue;
-        }
-        let resp = resp.unwrap(); // the actual response
-        if resp.response_code() == ResponseCode::NoError && resp.contains_answer() {
-            successes += 1;
-        }
-        if successes >= successes_needed {
-            return true;
+/// Validate a potential answer to a question.
+///
+/// `Derived` answers are looked up in the simulated world.
+/// Names the world knows nothing about cannot be checked, so the result is `None` for them.
+pub fn validate_answer(world: &World, expected: &Answer, got: &str) -> Option<bool> {
+    match expected {
+        Answer::Derived { record_type, query } => {
+            let name = parse_name(query)?;
+            if !world.knows(&name) {
+                return None;
+            }
+            let answer = world.resolve(&name, *record_type)?;
+            Some(
+                answer
+                    .iter()
+                    .filter_map(|item| item.data())
+                    .any(|item| answer_matches(item, got)),
+            )
         }
+        Answer::Preset { options } => Some(options.iter().any(|option| same_preset(option, got))),
     }
-
-    false
 }
 
-/// Validate a potential answer to a question,
-/// potentially by performing DNS lookups.
-pub async fn validate_answer(expected: &Answer, got: &str) -> Option<bool> {
-    match expected {
-        Answer::Derived { record_type, query } => {
-            let config = ResolverConfig::default();
-            let options = ResolverOpts::default();
-            let resolver = TokioAsyncResolver::tokio(config, options).ok()?;
+/// Whether the user's answer is one of the preset options.
+/// Case and a full stop at the end do not matter, so "Yes", "ДА" and "no." are all accepted.
+fn same_preset(option: &str, got: &str) -> bool {
+    let normalize = |answer: &str| answer.trim().trim_end_matches('.').to_lowercase();
+    normalize(option) == normalize(got)
+}
 
-            let answer = resolver.lookup(query, *record_type).await.ok()?;
-            Some(answer.iter().find(|item| item.to_string() == got).is_some())
-        }
-        Answer::Preset { options } => Some(options.contains(&got.to_owned())),
+/// Whether a record's data is what the user answered.
+/// For MX records, naming the mail exchanger is enough; the preference may be left out.
+fn answer_matches(item: &RData, got: &str) -> bool {
+    if item.to_string() == got {
+        return true;
+    }
+    match item {
+        RData::MX(mx) => parse_name(got).as_ref() == Some(mx.exchange()),
+        _ => false,
     }
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::world::WorldData;
 
-    #[tokio::test]
-    async fn test_validate_answer_preset() {
+    #[test]
+    fn test_validate_answer_preset() {
+        let world = World::generate(&WorldData::builtin(), b"test");
         assert_eq!(
             validate_answer(
+                &world,
                 &Answer::Preset {
                     options: vec!["1.2.3.4".to_string()]
                 },
                 "1.2.3.4"
-            )
-            .await,
+            ),
             Some(true)
         );
 
         assert_eq!(
             validate_answer(
+                &world,
                 &Answer::Preset {
                     options: vec!["1.2.3.4".to_string()]
                 },
                 "1.2.3.5"
-            )
-            .await,
+            ),
             Some(false)
         );
+
+        let yes = Answer::Preset {
+            options: vec!["yes".to_string(), "да".to_string()],
+        };
+        for got in ["Yes", "YES", "Да", "ДА", "yes."] {
+            assert_eq!(validate_answer(&world, &yes, got), Some(true), "{got}");
+        }
+        assert_eq!(validate_answer(&world, &yes, "No."), Some(false));
     }
 
-    #[tokio::test]
-    async fn test_validate_answer_derived() {
-        assert_eq!(
-            validate_answer(
-                &Answer::Derived {
-                    record_type: RecordType::A,
-                    query: "one.one.one.one.".to_string()
-                },
-                "1.1.1.1"
-            )
-            .await,
-            Some(true)
-        );
+    #[test]
+    fn test_validate_answer_derived_in_world() {
+        let world = World::generate(&WorldData::builtin(), b"test");
+        let name = parse_name("wikipedia.org").unwrap();
+        let expected = world.resolve(&name, RecordType::A).unwrap()[0]
+            .data()
+            .unwrap()
+            .to_string();
+        let answer = Answer::Derived {
+            record_type: RecordType::A,
+            query: "wikipedia.org".to_string(),
+        };
+        assert_eq!(validate_answer(&world, &answer, &expected), Some(true));
+        assert_eq!(validate_answer(&world, &answer, "1.2.3.4"), Some(false));
+
+        let mx = Answer::Derived {
+            record_type: RecordType::MX,
+            query: "gmail.com".to_string(),
+        };
+        assert_eq!(validate_answer(&world, &mx, "mx1.gmail.com"), Some(true));
         assert_eq!(
-            validate_answer(
-                &Answer::Derived {
-                    record_type: RecordType::A,
-                    query: "one.one.one.one.".to_string()
-                },
-                "1.0.0.1" // valid alternate A record
-            )
-            .await,
+            validate_answer(&world, &mx, "10 mx1.gmail.com."),
             Some(true)
         );
+    }
 
+    #[test]
+    fn test_validate_answer_derived() {
+        // The name is not in the simulated world, and the real one is never asked.
+        let world = World::generate(&WorldData::builtin(), b"test");
         assert_eq!(
             validate_answer(
+                &world,
                 &Answer::Derived {
                     record_type: RecordType::A,
                     query: "one.one.one.one.".to_string()
                 },
-                "1.2.3.4"
-            )
-            .await,
-            Some(false)
+                "1.1.1.1"
+            ),
+            None
         );
     }
 }
@@ -0,0 +1,152 @@
+//! Generating questions from templates and the world of a session.
+
+use std::net::Ipv4Addr;
+
+use clap::ValueEnum;
+use rand::{seq::SliceRandom, Rng};
+use serde::{Deserialize, Serialize};
+use trust_dns_client::rr::{Name, RData, RecordType};
+
+use crate::{
+    session::{Answer, Question},
+    world::{random_public_address, World},
+};
+
+/// Mailbox names used when a question needs an e-mail address.
+const MAILBOXES: &[&str] = &["example", "info", "ivanov", "petrova", "support", "student"];
+
+/// The kinds of questions that can be generated.
+#[derive(Serialize, Deserialize, ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum QuestionTemplate {
+    /// Which address serves the website of a domain (A record)
+    Website,
+    /// Which host accepts mail for an address (MX record)
+    Mail,
+    /// Whether a server is allowed to send mail for a domain (SPF in a TXT record)
+    Spf,
+}
+
+impl QuestionTemplate {
+    pub const ALL: [QuestionTemplate; 3] = [Self::Website, Self::Mail, Self::Spf];
+
+    /// Generate a question of this kind about a random domain of the world.
+    /// Returns `None` if the world has no domains to ask about.
+    pub fn generate(self, world: &World, rng: &mut impl Rng) -> Option<Question> {
+        let domain = world.domains().choose(rng)?;
+        let host = domain.to_string();
+        let host = host.trim_end_matches('.');
+        let question = match self {
+            QuestionTemplate::Website => Question::new(
+                format!("What IP address should I dial to reach https://www.{host}?"),
+                Answer::Derived {
+                    record_type: RecordType::A,
+                    query: format!("www.{host}"),
+                },
+            ),
+            QuestionTemplate::Mail => {
+                let mailbox = MAILBOXES.choose(rng).unwrap();
+                Question::new(
+                    format!("Which mail server should I deliver a letter for {mailbox}@{host} to?"),
+                    Answer::Derived {
+                        record_type: RecordType::MX,
+                        query: host.to_owned(),
+                    },
+                )
+            }
+            QuestionTemplate::Spf => {
+                let mailbox = MAILBOXES.choose(rng).unwrap();
+                let allowed = spf_addresses(world, domain);
+                let (sender, valid) = if !allowed.is_empty() && rng.gen_bool(0.5) {
+                    (*allowed.choose(rng).unwrap(), true)
+                } else {
+                    let mut sender = random_public_address(rng);
+                    while allowed.contains(&sender) {
+                        sender = random_public_address(rng);
+                    }
+                    (sender, false)
+                };
+                let options = if valid {
+                    ["yes", "да"]
+                } else {
+                    ["no", "нет"]
+                };
+                Question::new(
+                    format!(
+                        "We received a letter that says it was sent by {mailbox}@{host}. \
+                        It was passed to us by the server at {sender}. \
+                        According to SPF, is this letter really from {host}? (yes/no)"
+                    ),
+                    Answer::Preset {
+                        options: options.iter().map(|o| o.to_string()).collect(),
+                    },
+                )
+            }
+        };
+        Some(question)
+    }
+}
+
+/// A nudge in the right direction for practice sessions, which does not give the answer away.
+pub fn hint(answer: &Answer) -> String {
+    match answer {
+        Answer::Derived { record_type, query } => format!(
+            "Find the {record_type} records of {query}. Start by asking a root server, \
+            then follow the referrals in the authority section until a server answers with the AA flag set."
+        ),
+        Answer::Preset { .. } => "Work out which names the question is about and look up their records, \
+            starting from a root server. If the question is about who may send mail for a domain, \
+            its SPF policy is in its TXT records."
+            .to_owned(),
+    }
+}
+
+/// The addresses that the SPF policy of a domain allows to send mail.
+fn spf_addresses(world: &World, domain: &Name) -> Vec<Ipv4Addr> {
+    let Some(records) = world.resolve(domain, RecordType::TXT) else {
+        return vec![];
+    };
+    records
+        .iter()
+        .filter_map(|r| match r.data() {
+            Some(RData::TXT(txt)) => Some(txt.to_string()),
+            _ => None,
+        })
+        .filter(|txt| txt.starts_with("v=spf1"))
+        .flat_map(|txt| {
+            txt.split_whitespace()
+                .filter_map(|term| term.strip_prefix("ip4:")?.parse().ok())
+                .collect::<Vec<Ipv4Addr>>()
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{querying::validate_answer, world::WorldData};
+
+    #[test]
+    fn test_generated_answers_validate() {
+        let world = World::generate(&WorldData::builtin(), b"test");
+        let mut rng = world.rng_for("questions");
+
+        for template in QuestionTemplate::ALL {
+            let question = template.generate(&world, &mut rng).unwrap();
+            let expected = match &question.answer {
+                Answer::Derived { record_type, query } => {
+                    let name = crate::world::parse_name(query).unwrap();
+                    let records = world.resolve(&name, *record_type).unwrap();
+                    records[0].data().unwrap().to_string()
+                }
+                Answer::Preset { options } => options[0].clone(),
+            };
+            assert_eq!(
+                validate_answer(&world, &question.answer, &expected),
+                Some(true),
+                "{}",
+                question.text
+            );
+        }
+    }
+}
@@ -1,29 +1,310 @@
-use std::{net::IpAddr, path::PathBuf};
+use std::net::IpAddr;
 
-use chrono::{DateTime, Utc};
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use chrono::{DateTime, Duration, Utc};
 use serde::{Deserialize, Serialize};
-use tokio::{
-    fs::OpenOptions,
-    io::{AsyncReadExt, AsyncWriteExt},
-};
-use trust_dns_client::rr::RecordType;
+use trust_dns_client::{op::Message, rr::RecordType};
+
+pub use self::error::{CreateError, SessionError, StorageError, UpdateError};
+pub use self::key::{KeyError, SessionKey};
+pub use self::lock::SessionLocks;
+pub use self::scoring::{QuestionScore, Scoring};
+pub use self::sqlite::SqliteStore;
+pub use self::store::{FileStore, SessionStore};
+
+mod error;
+mod key;
+mod lock;
+mod migrate;
+mod scoring;
+mod sqlite;
+mod store;
 
 #[derive(Serialize, Deserialize)]
 pub struct Session {
+    pub schema_version: u32,
     pub user_requests: Vec<RequestLogEntry>,
     pub current_output_mode: OutputMode,
-    pub answers_remaining: usize,
+    /// Whether the session accepts any actions at all.
     pub can_answer: bool,
-    pub question: Question,
+    /// The questions of the session, in the order they are shown to the student.
+    pub questions: Vec<Question>,
+    /// Before this moment, the session does not accept any actions.
+    #[serde(default)]
+    pub opens_at: Option<DateTime<Utc>>,
+    /// How many minutes the student has, counted from when they first open the session.
+    #[serde(default)]
+    pub duration: Option<u32>,
+    /// From this moment on, the session does not accept any actions.
+    #[serde(default)]
+    pub closes_at: Option<DateTime<Utc>>,
+    /// When the student first opened the session within its time window.
+    /// Only recorded if the session has a duration.
+    #[serde(default)]
+    pub started_at: Option<DateTime<Utc>>,
+    /// Hash of the password that the student has to enter to open the session, if there is one.
+    /// See [`crate::password`].
+    #[serde(default)]
+    pub password_hash: Option<String>,
+    #[serde(default)]
+    pub mode: SessionMode,
+}
+
+/// How strict a session is with the student.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SessionMode {
+    /// Attempts are limited, and nothing is shown beyond the responses themselves.
+    #[default]
+    Exam,
+    /// Attempts are unlimited, and the student gets hints, explained responses
+    /// and the resolution path of every question as soon as it is answered.
+    Practice,
+}
+
+/// Whether a session accepts actions at some moment, according to its schedule.
+pub enum Window {
+    NotOpenYet {
+        opens_at: DateTime<Utc>,
+    },
+    /// The session is open, until the deadline if there is one.
+    Open {
+        deadline: Option<DateTime<Utc>>,
+    },
+    Over(LockReason),
+}
+
+/// The version of the stored session format that this version of the program writes.
+/// Sessions stored in older versions are upgraded when they are read, see [`migrate`].
+pub const SCHEMA_VERSION: u32 = 8;
+
+/// How many answers may be submitted to a new question.
+pub const DEFAULT_ANSWERS: usize = 5;
+
+impl Session {
+    /// A fresh session with the given questions.
+    pub fn new(questions: Vec<Question>) -> Self {
+        Self {
+            schema_version: SCHEMA_VERSION,
+            user_requests: vec![],
+            current_output_mode: OutputMode::Classic,
+            can_answer: true,
+            questions,
+            opens_at: None,
+            duration: None,
+            closes_at: None,
+            started_at: None,
+            password_hash: None,
+            mode: SessionMode::Exam,
+        }
+    }
+
+    /// Whether the session accepts actions at the given moment, according to its schedule.
+    /// This does not take into account whether the session has been locked for other reasons.
+    pub fn window(&self, now: DateTime<Utc>) -> Window {
+        if let Some(opens_at) = self.opens_at {
+            if now < opens_at {
+                return Window::NotOpenYet { opens_at };
+            }
+        }
+        let time_up = self
+            .started_at
+            .zip(self.duration)
+            .map(|(started_at, minutes)| started_at + Duration::minutes(minutes.into()));
+        if self.closes_at.is_some_and(|closes_at| now >= closes_at) {
+            return Window::Over(LockReason::DeadlinePassed);
+        }
+        if time_up.is_some_and(|time_up| now >= time_up) {
+            return Window::Over(LockReason::TimeUp);
+        }
+        let deadline = match (self.closes_at, time_up) {
+            (Some(closes_at), Some(time_up)) => Some(closes_at.min(time_up)),
+            (closes_at, time_up) => closes_at.or(time_up),
+        };
+        Window::Open { deadline }
+    }
+
+    /// Start counting the student's time, if the session has a duration and it has not started yet.
+    /// Returns whether the time was started.
+    pub fn start(&mut self, now: DateTime<Utc>) -> bool {
+        if self.duration.is_none() || self.started_at.is_some() {
+            return false;
+        }
+        self.started_at = Some(now);
+        true
+    }
+
+    /// Stop accepting any actions, returning the event that records why.
+    pub fn lock(&mut self, reason: LockReason) -> Event {
+        self.can_answer = false;
+        Event::Locked { reason }
+    }
+
+    /// The index of the question to show: the one with the given number, starting from 1,
+    /// or else the first one that is still open.
+    pub fn current_question(&self, number: Option<usize>) -> usize {
+        number
+            .filter(|number| (1..=self.questions.len()).contains(number))
+            .map(|number| number - 1)
+            .or_else(|| {
+                self.questions
+                    .iter()
+                    .position(|question| question.status == QuestionStatus::Open)
+            })
+            .unwrap_or(0)
+    }
+
+    /// How many DNS queries the student has made.
+    pub fn query_count(&self) -> usize {
+        self.user_requests
+            .iter()
+            .filter(|entry| matches!(entry.what, Event::Request { .. }))
+            .count()
+    }
+
+    /// How many answers were checked, that is, did not fail with an error.
+    pub fn attempts_used(&self) -> usize {
+        self.user_requests
+            .iter()
+            .filter(|entry| {
+                matches!(
+                    entry.what,
+                    Event::SubmitAnswer {
+                        status: AnswerStatus::Correct | AnswerStatus::Incorrect,
+                        ..
+                    }
+                )
+            })
+            .count()
+    }
+
+    /// Whether every question has been answered correctly or has run out of attempts.
+    pub fn is_finished(&self) -> bool {
+        self.questions
+            .iter()
+            .all(|question| question.status != QuestionStatus::Open)
+    }
+
+    /// Whether the student may see how the answer to a question is found:
+    /// once the session is locked, or in practice sessions once the question is answered.
+    pub fn reveals_path(&self, index: usize) -> bool {
+        let closed = self
+            .questions
+            .get(index)
+            .is_some_and(|question| question.status != QuestionStatus::Open);
+        (self.mode == SessionMode::Practice && closed) || !self.can_answer
+    }
+
+    /// Why the session was locked, if it was.
+    pub fn lock_reason(&self) -> Option<LockReason> {
+        self.user_requests
+            .iter()
+            .rev()
+            .find_map(|entry| match entry.what {
+                Event::Locked { reason } => Some(reason),
+                _ => None,
+            })
+    }
+
+    /// Parse a stored session, upgrading it if it was stored by an older version.
+    pub fn from_json(text: &str) -> Result<Self, SessionError> {
+        let document: serde_json::Value = serde_json::from_str(text)?;
+        if migrate::version(&document)? == SCHEMA_VERSION {
+            // Parsed straight from the text, so that errors point at the right line.
+            return Ok(serde_json::from_str(text)?);
+        }
+        Self::from_document(document)
+    }
+
+    /// Convert a stored session that has already been parsed as JSON,
+    /// upgrading it if it was stored by an older version.
+    pub fn from_document(mut document: serde_json::Value) -> Result<Self, SessionError> {
+        migrate::upgrade(&mut document)?;
+        Ok(serde_json::from_value(document)?)
+    }
+
+    /// Render every stored response again in the current output mode, for showing the session.
+    /// Responses stored without the message itself keep the text they were stored with.
+    pub fn render_responses(&mut self) {
+        let mode = self.current_output_mode;
+        for entry in &mut self.user_requests {
+            if let Event::Request {
+                response: ResponseResult::Ok { resp },
+                ..
+            } = &mut entry.what
+            {
+                if let Some(message) = resp.message() {
+                    resp.text = mode.render(&message);
+                    resp.mode = mode;
+                }
+            }
+        }
+    }
+
+    /// Forget everything the user has done, keeping only the questions,
+    /// and allow the given number of answers to each of them.
+    pub fn reset(&mut self, answers: usize) {
+        self.user_requests.clear();
+        self.current_output_mode = OutputMode::Classic;
+        self.started_at = None;
+        for question in &mut self.questions {
+            question.answers_remaining = answers;
+            question.score = None;
+            question.status = if answers > 0 {
+                QuestionStatus::Open
+            } else {
+                QuestionStatus::OutOfAttempts
+            };
+        }
+        self.can_answer = !self.is_finished();
+    }
 }
 
 #[derive(Serialize, Deserialize)]
 pub struct Question {
     pub text: String,
     pub answer: Answer,
+    #[serde(default = "default_answers")]
+    pub answers_remaining: usize,
+    #[serde(default)]
+    pub status: QuestionStatus,
+    #[serde(default)]
+    pub scoring: Scoring,
+    /// The grade the question was given when it closed, kept so that
+    /// changing its scoring afterwards does not change grades already given.
+    #[serde(default)]
+    pub score: Option<QuestionScore>,
 }
 
-#[derive(Serialize, Deserialize)]
+impl Question {
+    /// A new question, with the default attempt limit.
+    pub fn new(text: String, answer: Answer) -> Self {
+        Self {
+            text,
+            answer,
+            answers_remaining: DEFAULT_ANSWERS,
+            status: QuestionStatus::Open,
+            scoring: Scoring::default(),
+            score: None,
+        }
+    }
+}
+
+fn default_answers() -> usize {
+    DEFAULT_ANSWERS
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum QuestionStatus {
+    /// Answers are accepted.
+    #[default]
+    Open,
+    /// The question was answered correctly.
+    Correct,
+    /// All of the attempts to answer were used up.
+    OutOfAttempts,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
 #[serde(tag = "type")]
 pub enum Answer {
     Derived {
@@ -35,35 +316,18 @@ pub enum Answer {
     },
 }
 
-pub async fn get_session(key: &str) -> Option<Session> {
-    let mut path = PathBuf::new();
-    path.push("sessions");
-    path.push(key);
-    path.set_extension("json");
-    let mut file = tokio::fs::File::open(path).await.ok()?;
-    let mut data = String::new();
-    file.read_to_string(&mut data).await.ok()?;
-    serde_json::from_str(&data).ok()?
-}
-
-pub async fn set_session(key: &str, new_session: Session) -> Option<()> {
-    let mut path = PathBuf::new();
-    path.push("sessions");
-    path.push(key);
-    path.set_extension("json");
-    let mut options = OpenOptions::new();
-    let mut file = options.write(true).open(path).await.ok()?;
-    let data = serde_json::to_vec_pretty(&new_session).ok()?;
-    file.write_all(&data).await.ok()?;
-    Some(())
-}
-
 #[derive(Serialize, Deserialize, Clone, Copy, Debug)]
 pub enum OutputMode {
     /// As Display
     Classic,
     /// As Debug
     Rust,
+    /// As JSON, following RFC 8427
+    Json,
+    /// Explained in plain English
+    ExplainEn,
+    /// Explained in plain Russian
+    ExplainRu,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -76,6 +340,8 @@ pub struct RequestLogEntry {
 #[serde(tag = "type")]
 pub enum Event {
     Request {
+        /// Index of the question that was shown when the query was made
+        question: usize,
         request: Request,
         response: ResponseResult,
     },
@@ -83,12 +349,28 @@ pub enum Event {
         new_mode: OutputMode,
     },
     SubmitAnswer {
+        /// Index of the question in the session
+        question: usize,
         answer: String,
         status: AnswerStatus,
     },
+    /// The session stopped accepting actions.
+    Locked {
+        reason: LockReason,
+    },
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LockReason {
+    /// Every question was answered correctly or ran out of attempts.
+    Finished,
+    /// The session's closing time has passed.
+    DeadlinePassed,
+    /// The student's time since opening the session is over.
+    TimeUp,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
 pub enum AnswerStatus {
     Correct,
     Incorrect,
@@ -123,6 +405,171 @@ pub enum ResponseResult {
 
 #[derive(Serialize, Deserialize)]
 pub struct Response {
+    /// The response as it was shown when it was received.
     pub text: String,
     pub mode: OutputMode,
+    /// The message in the DNS wire format, encoded with base64.
+    /// Unset for responses stored before version 7.
+    pub wire: Option<String>,
+}
+
+impl Response {
+    /// Keep a server's response, shown in the given output mode.
+    pub fn new(message: &Message, mode: OutputMode) -> Self {
+        Self {
+            text: mode.render(message),
+            mode,
+            wire: message.to_vec().ok().map(|bytes| BASE64.encode(bytes)),
+        }
+    }
+
+    /// The message as the server sent it, if it was stored.
+    pub fn message(&self) -> Option<Message> {
+        let bytes = BASE64.decode(self.wire.as_ref()?).ok()?;
+        Message::from_vec(&bytes).ok()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_finished_when_no_question_is_open() {
+        let question = || Question::new("?".to_string(), Answer::Preset { options: vec![] });
+        let mut session = Session::new(vec![question(), question()]);
+        assert!(!session.is_finished());
+        session.questions[0].status = QuestionStatus::Correct;
+        assert!(!session.is_finished());
+        session.questions[1].status = QuestionStatus::OutOfAttempts;
+        assert!(session.is_finished());
+
+        session.can_answer = false;
+        session.reset(3);
+        assert!(session.can_answer);
+        assert!(session
+            .questions
+            .iter()
+            .all(|q| q.status == QuestionStatus::Open && q.answers_remaining == 3));
+        session.reset(0);
+        assert!(!session.can_answer);
+    }
+
+    #[test]
+    fn test_reveals_path() {
+        let question = || Question::new("?".to_string(), Answer::Preset { options: vec![] });
+        let mut session = Session::new(vec![question(), question()]);
+        session.questions[0].status = QuestionStatus::Correct;
+        assert!(!session.reveals_path(0));
+        session.can_answer = false;
+        assert!(session.reveals_path(0));
+        assert!(session.reveals_path(1));
+
+        session.can_answer = true;
+        session.mode = SessionMode::Practice;
+        assert!(session.reveals_path(0));
+        assert!(!session.reveals_path(1));
+    }
+
+    #[test]
+    fn test_render_responses() {
+        use trust_dns_client::{op::Query, rr::Name};
+
+        let mut message = Message::new();
+        message.add_query(Query::query(
+            Name::from_ascii("example.com.").unwrap(),
+            RecordType::A,
+        ));
+        let request = || Request {
+            server_ip: "1.2.3.4".to_string(),
+            name: "example.com".to_string(),
+            record_type: RecordType::A,
+        };
+        let mut old = Response::new(&message, OutputMode::Classic);
+        old.wire = None;
+        let mut session = Session::new(vec![]);
+        for resp in [Response::new(&message, OutputMode::Classic), old] {
+            session.user_requests.push(RequestLogEntry {
+                when: Utc::now(),
+                what: Event::Request {
+                    question: 0,
+                    request: request(),
+                    response: ResponseResult::Ok { resp },
+                },
+            });
+        }
+
+        session.current_output_mode = OutputMode::Rust;
+        session.render_responses();
+        let texts: Vec<_> = session
+            .user_requests
+            .iter()
+            .map(|entry| match &entry.what {
+                Event::Request {
+                    response: ResponseResult::Ok { resp },
+                    ..
+                } => resp,
+                _ => unreachable!(),
+            })
+            .collect();
+        // Header counts are only filled in when the message is encoded.
+        let sent = Message::from_vec(&message.to_vec().unwrap()).unwrap();
+        assert_eq!(texts[0].text, OutputMode::Rust.render(&sent));
+        assert!(matches!(texts[0].mode, OutputMode::Rust));
+        assert_eq!(texts[1].text, OutputMode::Classic.render(&message));
+        assert!(matches!(texts[1].mode, OutputMode::Classic));
+    }
+
+    #[test]
+    fn test_window() {
+        let mut session = Session::new(vec![Question::new(
+            "?".to_string(),
+            Answer::Preset {
+                options: vec!["yes".to_string()],
+            },
+        )]);
+        let at =
+            |minute: i64| DateTime::<Utc>::MIN_UTC + Duration::days(1) + Duration::minutes(minute);
+        assert!(matches!(
+            session.window(at(0)),
+            Window::Open { deadline: None }
+        ));
+
+        session.opens_at = Some(at(10));
+        session.closes_at = Some(at(100));
+        session.duration = Some(30);
+        assert!(matches!(session.window(at(0)), Window::NotOpenYet { .. }));
+        assert!(
+            matches!(session.window(at(10)), Window::Open { deadline } if deadline == Some(at(100)))
+        );
+
+        // The student's own time ends before the session closes...
+        assert!(session.start(at(20)));
+        assert!(!session.start(at(21)));
+        assert!(
+            matches!(session.window(at(49)), Window::Open { deadline } if deadline == Some(at(50)))
+        );
+        assert!(matches!(
+            session.window(at(50)),
+            Window::Over(LockReason::TimeUp)
+        ));
+
+        // ...unless they start late.
+        session.started_at = Some(at(90));
+        assert!(
+            matches!(session.window(at(99)), Window::Open { deadline } if deadline == Some(at(100)))
+        );
+        assert!(matches!(
+            session.window(at(100)),
+            Window::Over(LockReason::DeadlinePassed)
+        ));
+
+        let event = session.lock(LockReason::DeadlinePassed);
+        assert!(!session.can_answer);
+        session.user_requests.push(RequestLogEntry {
+            when: at(100),
+            what: event,
+        });
+        assert_eq!(session.lock_reason(), Some(LockReason::DeadlinePassed));
+    }
 }
@@ -0,0 +1,193 @@
+use std::fmt;
+
+/// Why a session could not be read.
+#[derive(Debug)]
+pub enum SessionError {
+    /// There is no session with this key.
+    NotFound,
+    /// The storage could not be read: a file or the database failed.
+    Io(StorageError),
+    /// The stored session is not valid JSON, or does not describe a session.
+    /// The line is 0 if the problem is not at any particular place.
+    Corrupt {
+        line: usize,
+        column: usize,
+        message: String,
+    },
+    /// The stored session is in a format that this version does not understand.
+    VersionMismatch { found: u32, supported: u32 },
+}
+
+/// Why a session could not be created.
+#[derive(Debug)]
+pub enum CreateError {
+    /// There already is a session with this key; it was left as it is.
+    AlreadyExists,
+    Storage(StorageError),
+}
+
+/// Why a session could not be updated.
+#[derive(Debug)]
+pub enum UpdateError {
+    /// There is no session with this key; none was created.
+    NotFound,
+    Storage(StorageError),
+}
+
+/// The storage itself failed, regardless of what was asked of it.
+#[derive(Debug)]
+pub enum StorageError {
+    Io(std::io::Error),
+    Database(rusqlite::Error),
+    /// The session could not be converted to JSON.
+    Encoding(serde_json::Error),
+}
+
+impl fmt::Display for SessionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SessionError::NotFound => write!(f, "there is no session with this key"),
+            SessionError::Io(e) => write!(f, "{e}"),
+            SessionError::Corrupt { line: 0, message, .. } => {
+                write!(f, "invalid session data: {message}")
+            }
+            SessionError::Corrupt {
+                line,
+                column,
+                message,
+            } => write!(
+                f,
+                "invalid session data at line {line}, column {column}: {message}"
+            ),
+            SessionError::VersionMismatch { found, supported } => write!(
+                f,
+                "session is stored in format version {found}, but only version {supported} is supported"
+            ),
+        }
+    }
+}
+
+impl fmt::Display for CreateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CreateError::AlreadyExists => write!(f, "a session with this key already exists"),
+            CreateError::Storage(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl fmt::Display for UpdateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            UpdateError::NotFound => write!(f, "there is no session with this key"),
+            UpdateError::Storage(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl fmt::Display for StorageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StorageError::Io(e) => write!(f, "I/O error: {e}"),
+            StorageError::Database(e) => write!(f, "database error: {e}"),
+            StorageError::Encoding(e) => write!(f, "could not encode the session: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for SessionError {}
+impl std::error::Error for CreateError {}
+impl std::error::Error for UpdateError {}
+impl std::error::Error for StorageError {}
+
+impl From<std::io::Error> for StorageError {
+    fn from(e: std::io::Error) -> Self {
+        StorageError::Io(e)
+    }
+}
+
+impl From<rusqlite::Error> for StorageError {
+    fn from(e: rusqlite::Error) -> Self {
+        StorageError::Database(e)
+    }
+}
+
+impl From<serde_json::Error> for StorageError {
+    fn from(e: serde_json::Error) -> Self {
+        StorageError::Encoding(e)
+    }
+}
+
+/// Let `?` turn any storage failure into the error of an operation.
+macro_rules! from_storage_errors {
+    ($($error:ident),*) => {$(
+        impl From<StorageError> for $error {
+            fn from(e: StorageError) -> Self {
+                $error::Storage(e)
+            }
+        }
+
+        impl From<std::io::Error> for $error {
+            fn from(e: std::io::Error) -> Self {
+                $error::Storage(e.into())
+            }
+        }
+
+        impl From<rusqlite::Error> for $error {
+            fn from(e: rusqlite::Error) -> Self {
+                $error::Storage(e.into())
+            }
+        }
+
+        impl From<serde_json::Error> for $error {
+            fn from(e: serde_json::Error) -> Self {
+                $error::Storage(e.into())
+            }
+        }
+    )*};
+}
+
+from_storage_errors!(CreateError, UpdateError);
+
+impl From<StorageError> for SessionError {
+    fn from(e: StorageError) -> Self {
+        SessionError::Io(e)
+    }
+}
+
+impl From<std::io::Error> for SessionError {
+    fn from(e: std::io::Error) -> Self {
+        SessionError::Io(e.into())
+    }
+}
+
+impl From<rusqlite::Error> for SessionError {
+    fn from(e: rusqlite::Error) -> Self {
+        SessionError::Io(e.into())
+    }
+}
+
+/// When reading, JSON that cannot be decoded means the stored session is broken,
+/// not that the storage failed.
+impl From<serde_json::Error> for SessionError {
+    fn from(e: serde_json::Error) -> Self {
+        // The message of a JSON error ends with its position, which is kept separately.
+        let message = e.to_string();
+        let position = format!(" at line {} column {}", e.line(), e.column());
+        SessionError::Corrupt {
+            line: e.line(),
+            column: e.column(),
+            message: message.trim_end_matches(&position).to_owned(),
+        }
+    }
+}
+
+/// Saving a session that was just read fails the same way as reading a missing one.
+impl From<UpdateError> for SessionError {
+    fn from(e: UpdateError) -> Self {
+        match e {
+            UpdateError::NotFound => SessionError::NotFound,
+            UpdateError::Storage(e) => SessionError::Io(e),
+        }
+    }
+}
@@ -0,0 +1,162 @@
+use std::{fmt, str::FromStr};
+
+use serde::{Deserialize, Serialize};
+
+/// The longest session key that is accepted.
+pub const MAX_KEY_LENGTH: usize = 64;
+
+/// Keys that the fixed pages of the web server take up:
+/// `/admin` would hide the session `admin`, and `/admin/login` and the like
+/// would hide the teacher's view of the session `login`.
+const RESERVED_KEYS: [&str; 4] = ["admin", "login", "logout", "grades"];
+
+/// The key of a session, as typed in by the student.
+///
+/// Keys end up in file names and URLs, so only ASCII letters, digits, `-` and `_` are allowed:
+/// a valid key can never point outside of the sessions directory.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[serde(try_from = "String", into = "String")]
+pub struct SessionKey(String);
+
+/// Why a string is not a valid session key.
+#[derive(Debug, PartialEq, Eq)]
+pub enum KeyError {
+    Empty,
+    TooLong,
+    InvalidCharacter(char),
+    Reserved,
+}
+
+impl SessionKey {
+    pub fn new(key: String) -> Result<Self, KeyError> {
+        if key.is_empty() {
+            return Err(KeyError::Empty);
+        }
+        if key.len() > MAX_KEY_LENGTH {
+            return Err(KeyError::TooLong);
+        }
+        if let Some(c) = key
+            .chars()
+            .find(|c| !(c.is_ascii_alphanumeric() || *c == '-' || *c == '_'))
+        {
+            return Err(KeyError::InvalidCharacter(c));
+        }
+        if RESERVED_KEYS.contains(&key.as_str()) {
+            return Err(KeyError::Reserved);
+        }
+        Ok(Self(key))
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Display for SessionKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl FromStr for SessionKey {
+    type Err = KeyError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::new(s.to_owned())
+    }
+}
+
+impl TryFrom<String> for SessionKey {
+    type Error = KeyError;
+
+    fn try_from(key: String) -> Result<Self, Self::Error> {
+        Self::new(key)
+    }
+}
+
+impl From<SessionKey> for String {
+    fn from(key: SessionKey) -> Self {
+        key.0
+    }
+}
+
+impl fmt::Display for KeyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            KeyError::Empty => write!(f, "the session key is empty"),
+            KeyError::TooLong => write!(
+                f,
+                "the session key is longer than {MAX_KEY_LENGTH} characters"
+            ),
+            KeyError::InvalidCharacter(c) => write!(
+                f,
+                "the session key contains {c:?}, but only latin letters, digits, '-' and '_' are allowed"
+            ),
+            KeyError::Reserved => write!(f, "the session key is taken by a page of the server"),
+        }
+    }
+}
+
+impl std::error::Error for KeyError {}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_valid_keys() {
+        for key in [
+            "example1",
+            "group1-ab12cd34",
+            "A_b",
+            &"x".repeat(MAX_KEY_LENGTH),
+        ] {
+            assert_eq!(key.parse::<SessionKey>().unwrap().as_str(), key);
+        }
+    }
+
+    #[test]
+    fn test_malicious_keys() {
+        for key in [
+            "../secret",
+            "..%2Fsecret",
+            "..",
+            "/etc/passwd",
+            "sessions/../../secret",
+            "..\\secret",
+            "C:secret",
+            "example1.json",
+            ".hidden",
+            "key\0.json",
+            "key\n",
+            " key",
+            "%2e%2e",
+            "<script>",
+            "ключ",
+            "ｋｅｙ",
+        ] {
+            assert!(key.parse::<SessionKey>().is_err(), "{key:?} was accepted");
+        }
+        assert_eq!("".parse::<SessionKey>(), Err(KeyError::Empty));
+        assert_eq!(
+            "x".repeat(MAX_KEY_LENGTH + 1).parse::<SessionKey>(),
+            Err(KeyError::TooLong)
+        );
+        // Non-ASCII characters take more than one byte, so the length limit is checked in bytes.
+        assert_eq!(
+            "я".repeat(MAX_KEY_LENGTH / 2 + 1).parse::<SessionKey>(),
+            Err(KeyError::TooLong)
+        );
+        // These would be shadowed by the pages of the web server.
+        for key in ["admin", "login", "logout", "grades"] {
+            assert_eq!(key.parse::<SessionKey>(), Err(KeyError::Reserved));
+        }
+        assert!("admin1".parse::<SessionKey>().is_ok());
+    }
+
+    #[test]
+    fn test_deserialize_rejects_invalid_keys() {
+        assert!(serde_json::from_str::<SessionKey>(r#""example1""#).is_ok());
+        assert!(serde_json::from_str::<SessionKey>(r#""../secret""#).is_err());
+    }
+}
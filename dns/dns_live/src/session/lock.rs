@@ -0,0 +1,29 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use tokio::sync::{Mutex as AsyncMutex, OwnedMutexGuard};
+
+use super::SessionKey;
+
+/// Per-session locks, so that requests to the same session are handled one at a time
+/// and do not overwrite each other's changes.
+#[derive(Default)]
+pub struct SessionLocks {
+    locks: Mutex<HashMap<SessionKey, Arc<AsyncMutex<()>>>>,
+}
+
+impl SessionLocks {
+    /// Wait until no one else is working with this session.
+    /// The session stays locked until the returned guard is dropped.
+    pub async fn lock(&self, key: &SessionKey) -> OwnedMutexGuard<()> {
+        let lock = {
+            let mut locks = self.locks.lock().unwrap();
+            // Forget the locks that nobody is holding or waiting for.
+            locks.retain(|_, lock| Arc::strong_count(lock) > 1);
+            locks.entry(key.clone()).or_default().clone()
+        };
+        lock.lock_owned().await
+    }
+}
@@ -0,0 +1,277 @@
+//! Upgrading sessions stored by older versions of the program.
+//!
+//! Every change to the stored format bumps [`SCHEMA_VERSION`] and adds a function to [`MIGRATIONS`]
+//! that turns a session of the previous version into the new one,
+//! along with a fixture of the previous version in `fixtures/`.
+
+use serde_json::{Map, Value};
+
+use super::{Scoring, SessionError, SCHEMA_VERSION};
+
+/// Upgrades of the stored format, in order: the first one upgrades version 1 to version 2, and so on.
+const MIGRATIONS: &[fn(&mut Map<String, Value>)] = &[
+    v1_to_v2, v2_to_v3, v3_to_v4, v4_to_v5, v5_to_v6, v6_to_v7, v7_to_v8,
+];
+
+/// Version 1 is the original format, which did not say what version it is.
+/// Version 2 adds `schema_version` and nothing else.
+fn v1_to_v2(_session: &mut Map<String, Value>) {}
+
+/// Version 3 adds the schedule: `opens_at`, `duration`, `closes_at` and `started_at`,
+/// which are all unset for older sessions.
+fn v2_to_v3(session: &mut Map<String, Value>) {
+    for field in ["opens_at", "duration", "closes_at", "started_at"] {
+        session.insert(field.to_owned(), Value::Null);
+    }
+}
+
+/// Version 4 allows several questions per session: `question` becomes the only one of `questions`,
+/// and takes the session's `answers_remaining` along with a `status` of its own.
+/// Answers in the log say which question they are for, and a session is locked for being `Finished`
+/// rather than for how its only question was finished.
+fn v3_to_v4(session: &mut Map<String, Value>) {
+    let mut correct = false;
+    let log = session
+        .get_mut("user_requests")
+        .and_then(Value::as_array_mut);
+    for entry in log.into_iter().flatten() {
+        let Some(what) = entry.get_mut("what").and_then(Value::as_object_mut) else {
+            continue;
+        };
+        match what.get("type").and_then(Value::as_str) {
+            Some("SubmitAnswer") => {
+                correct |= what.get("status").and_then(Value::as_str) == Some("Correct");
+                what.insert("question".to_owned(), Value::from(0));
+            }
+            Some("Locked") => {
+                let reason = what.get("reason").and_then(Value::as_str);
+                if matches!(reason, Some("Answered" | "OutOfAttempts")) {
+                    what.insert("reason".to_owned(), Value::from("Finished"));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let answers_remaining = session.remove("answers_remaining");
+    let status = if correct {
+        "Correct"
+    } else if answers_remaining.as_ref().and_then(Value::as_u64) == Some(0) {
+        "OutOfAttempts"
+    } else {
+        "Open"
+    };
+    if let Some(Value::Object(mut question)) = session.remove("question") {
+        if let Some(answers_remaining) = answers_remaining {
+            question.insert("answers_remaining".to_owned(), answers_remaining);
+        }
+        question.insert("status".to_owned(), Value::from(status));
+        session.insert("questions".to_owned(), Value::from(vec![question]));
+    }
+}
+
+/// Version 5 adds grading: every question gets the default `scoring`,
+/// and every query says which question it was made for.
+/// Older queries are taken to be for the question that was shown by default at the time:
+/// the first one that had not been answered correctly yet.
+fn v4_to_v5(session: &mut Map<String, Value>) {
+    let questions = session.get_mut("questions").and_then(Value::as_array_mut);
+    let mut answered = vec![];
+    for question in questions.into_iter().flatten() {
+        if let Value::Object(question) = question {
+            let scoring = serde_json::to_value(Scoring::default()).unwrap();
+            question.insert("scoring".to_owned(), scoring);
+        }
+        answered.push(false);
+    }
+    let log = session
+        .get_mut("user_requests")
+        .and_then(Value::as_array_mut);
+    for entry in log.into_iter().flatten() {
+        let Some(what) = entry.get_mut("what").and_then(Value::as_object_mut) else {
+            continue;
+        };
+        match what.get("type").and_then(Value::as_str) {
+            Some("Request") => {
+                let shown = answered.iter().position(|answered| !answered).unwrap_or(0);
+                what.insert("question".to_owned(), Value::from(shown));
+            }
+            Some("SubmitAnswer")
+                if what.get("status").and_then(Value::as_str) == Some("Correct") =>
+            {
+                let question = what.get("question").and_then(Value::as_u64);
+                if let Some(answered) = question.and_then(|q| answered.get_mut(q as usize)) {
+                    *answered = true;
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Version 6 adds `password_hash`, which is unset for older sessions.
+fn v5_to_v6(session: &mut Map<String, Value>) {
+    session.insert("password_hash".to_owned(), Value::Null);
+}
+
+/// Version 7 keeps the message of every response in the wire format, as `wire`;
+/// older responses only have their text.
+fn v6_to_v7(session: &mut Map<String, Value>) {
+    let log = session
+        .get_mut("user_requests")
+        .and_then(Value::as_array_mut);
+    for entry in log.into_iter().flatten() {
+        let response = entry
+            .pointer_mut("/what/response")
+            .and_then(Value::as_object_mut)
+            .filter(|response| response.get("type").and_then(Value::as_str) == Some("Ok"))
+            .and_then(|response| response.get_mut("resp"))
+            .and_then(Value::as_object_mut);
+        if let Some(resp) = response {
+            resp.insert("wire".to_owned(), Value::Null);
+        }
+    }
+}
+
+/// Version 8 adds the session's `mode`; older sessions are exams.
+fn v7_to_v8(session: &mut Map<String, Value>) {
+    session.insert("mode".to_owned(), Value::from("Exam"));
+}
+
+/// The version of the stored format that a session is in.
+pub(super) fn version(document: &Value) -> Result<u32, SessionError> {
+    let Value::Object(fields) = document else {
+        return Err(corrupt("a session must be a JSON object"));
+    };
+    match fields.get("schema_version") {
+        None => Ok(1),
+        Some(version) => version
+            .as_u64()
+            .and_then(|version| u32::try_from(version).ok())
+            .ok_or_else(|| corrupt("schema_version must be a whole number")),
+    }
+}
+
+/// Bring a session stored in any known version of the format up to the current one.
+pub(super) fn upgrade(document: &mut Value) -> Result<(), SessionError> {
+    let found = version(document)?;
+    if found == 0 || found > SCHEMA_VERSION {
+        return Err(SessionError::VersionMismatch {
+            found,
+            supported: SCHEMA_VERSION,
+        });
+    }
+    let Value::Object(fields) = document else {
+        unreachable!("checked by version()");
+    };
+    for (from, migration) in MIGRATIONS.iter().enumerate().skip(found as usize - 1) {
+        migration(fields);
+        fields.insert("schema_version".to_owned(), Value::from(from as u32 + 2));
+    }
+    Ok(())
+}
+
+/// A problem with the structure of a session, rather than with its JSON syntax.
+/// Such problems are not tied to a position in the text.
+fn corrupt(message: &str) -> SessionError {
+    SessionError::Corrupt {
+        line: 0,
+        column: 0,
+        message: message.to_owned(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::session::{
+        AnswerStatus, Event, LockReason, OutputMode, QuestionStatus, Session, SessionMode,
+    };
+
+    /// A session in every version of the format, oldest first.
+    const FIXTURES: &[&str] = &[
+        include_str!("fixtures/v1.json"),
+        include_str!("fixtures/v2.json"),
+        include_str!("fixtures/v3.json"),
+        include_str!("fixtures/v4.json"),
+        include_str!("fixtures/v5.json"),
+        include_str!("fixtures/v6.json"),
+        include_str!("fixtures/v7.json"),
+        include_str!("fixtures/v8.json"),
+    ];
+
+    #[test]
+    fn test_fixtures_cover_every_version() {
+        assert_eq!(MIGRATIONS.len() + 1, SCHEMA_VERSION as usize);
+        assert_eq!(FIXTURES.len(), SCHEMA_VERSION as usize);
+        for (i, fixture) in FIXTURES.iter().enumerate() {
+            let document: Value = serde_json::from_str(fixture).unwrap();
+            assert_eq!(version(&document).unwrap(), i as u32 + 1);
+        }
+    }
+
+    #[test]
+    fn test_upgrade_fixtures() {
+        for fixture in FIXTURES {
+            let session = Session::from_json(fixture).unwrap();
+            assert_eq!(session.schema_version, SCHEMA_VERSION);
+            assert_eq!(session.questions.len(), 1);
+            assert_eq!(session.questions[0].answers_remaining, 4);
+            assert_eq!(session.questions[0].status, QuestionStatus::Open);
+            assert_eq!(session.questions[0].scoring, Scoring::default());
+            assert_eq!(session.mode, SessionMode::Exam);
+            assert!(session.can_answer);
+            assert!(matches!(session.current_output_mode, OutputMode::Rust));
+            assert_eq!(session.user_requests.len(), 3);
+            assert!(matches!(
+                session.user_requests[0].what,
+                Event::Request { question: 0, .. }
+            ));
+            assert!(matches!(
+                session.user_requests[2].what,
+                Event::SubmitAnswer {
+                    question: 0,
+                    status: AnswerStatus::Incorrect,
+                    ..
+                }
+            ));
+
+            // Once saved, the session is stored in the current version.
+            let saved = serde_json::to_value(&session).unwrap();
+            assert_eq!(version(&saved).unwrap(), SCHEMA_VERSION);
+        }
+    }
+
+    #[test]
+    fn test_upgrade_finished_session() {
+        let mut document: Value = serde_json::from_str(FIXTURES[2]).unwrap();
+        let fields = document.as_object_mut().unwrap();
+        fields.insert("can_answer".to_owned(), Value::from(false));
+        fields.insert("answers_remaining".to_owned(), Value::from(0));
+        let log = fields["user_requests"].as_array_mut().unwrap();
+        log.push(serde_json::json!({
+            "when": "2023-03-10T09:17:00Z",
+            "what": {"type": "Locked", "reason": "OutOfAttempts"}
+        }));
+
+        let session = Session::from_document(document).unwrap();
+        assert_eq!(session.questions[0].status, QuestionStatus::OutOfAttempts);
+        assert_eq!(session.lock_reason(), Some(LockReason::Finished));
+    }
+
+    #[test]
+    fn test_unknown_versions() {
+        for text in [r#"{"schema_version": 0}"#, r#"{"schema_version": 1000}"#] {
+            assert!(matches!(
+                Session::from_json(text),
+                Err(SessionError::VersionMismatch { .. })
+            ));
+        }
+        for text in [r#"{"schema_version": "2"}"#, "[]"] {
+            assert!(matches!(
+                Session::from_json(text),
+                Err(SessionError::Corrupt { .. })
+            ));
+        }
+    }
+}
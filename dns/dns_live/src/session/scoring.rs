@@ -0,0 +1,269 @@
+//! Grading the questions of a session from its log.
+
+use serde::{Deserialize, Serialize};
+
+use super::{AnswerStatus, Event, QuestionStatus, RequestLogEntry, Session};
+
+/// How a question is graded.
+///
+/// A correct answer is worth `points`, minus the penalties for incorrect answers
+/// and for queries beyond the budget, but never less than zero.
+/// Every query of the budget that was not needed adds a bonus on top.
+/// A question that was not answered correctly is worth nothing.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(default)]
+pub struct Scoring {
+    /// Points for a correct answer.
+    pub points: f64,
+    /// Taken off for every incorrect answer.
+    pub wrong_answer_penalty: f64,
+    /// How many queries should be enough to answer the question; no limit if not set.
+    pub query_budget: Option<usize>,
+    /// Taken off for every query beyond the budget.
+    pub over_budget_penalty: f64,
+    /// Added for every query of the budget that was not made.
+    pub under_budget_bonus: f64,
+}
+
+impl Default for Scoring {
+    fn default() -> Self {
+        Self {
+            points: 10.0,
+            wrong_answer_penalty: 2.0,
+            query_budget: None,
+            over_budget_penalty: 0.5,
+            under_budget_bonus: 0.0,
+        }
+    }
+}
+
+/// The grade of a question, and what it was computed from.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct QuestionScore {
+    pub status: QuestionStatus,
+    /// Queries made while the question was open and shown to the student.
+    pub queries: usize,
+    pub wrong_answers: usize,
+    pub points: f64,
+    /// The most points that the question could be worth.
+    pub max_points: f64,
+}
+
+impl Scoring {
+    /// Points for a question, given how it was finished and how it got there.
+    pub fn score(&self, status: QuestionStatus, wrong_answers: usize, queries: usize) -> f64 {
+        if status != QuestionStatus::Correct {
+            return 0.0;
+        }
+        let (over_budget, under_budget) = match self.query_budget {
+            Some(budget) => (
+                queries.saturating_sub(budget),
+                budget.saturating_sub(queries),
+            ),
+            None => (0, 0),
+        };
+        let points = self.points
+            - self.wrong_answer_penalty * wrong_answers as f64
+            - self.over_budget_penalty * over_budget as f64;
+        points.max(0.0) + self.under_budget_bonus * under_budget as f64
+    }
+
+    /// The most points that a question could be worth: answered at once, without any queries.
+    pub fn max_points(&self) -> f64 {
+        self.score(QuestionStatus::Correct, 0, 0)
+    }
+}
+
+impl Session {
+    /// Grade every question of the session.
+    /// A question that has closed keeps the grade stored when it closed;
+    /// the others are graded from the log.
+    pub fn scores(&self) -> Vec<QuestionScore> {
+        self.grade(&[])
+    }
+
+    /// Store the grade of every question that has closed since grades were last stored,
+    /// counting the `pending` entries that are about to be added to the log.
+    pub fn store_scores(&mut self, pending: &[RequestLogEntry]) {
+        let scores = self.grade(pending);
+        for (question, score) in self.questions.iter_mut().zip(scores) {
+            if question.status != QuestionStatus::Open && question.score.is_none() {
+                question.score = Some(score);
+            }
+        }
+    }
+
+    fn grade(&self, pending: &[RequestLogEntry]) -> Vec<QuestionScore> {
+        let mut scores: Vec<QuestionScore> = self
+            .questions
+            .iter()
+            .map(|question| QuestionScore {
+                status: question.status,
+                queries: 0,
+                wrong_answers: 0,
+                points: 0.0,
+                max_points: question.scoring.max_points(),
+            })
+            .collect();
+        // Whether each question has been finished at this point of the log.
+        let mut finished = vec![false; scores.len()];
+        for entry in self.user_requests.iter().chain(pending) {
+            match &entry.what {
+                Event::Request { question, .. } => {
+                    if let Some(score) = scores.get_mut(*question) {
+                        if !finished[*question] {
+                            score.queries += 1;
+                        }
+                    }
+                }
+                Event::SubmitAnswer {
+                    question, status, ..
+                } => {
+                    let Some(score) = scores.get_mut(*question) else {
+                        continue;
+                    };
+                    match status {
+                        AnswerStatus::Correct => finished[*question] = true,
+                        AnswerStatus::Incorrect => score.wrong_answers += 1,
+                        AnswerStatus::Error => {}
+                    }
+                }
+                Event::SwitchOutputMode { .. } | Event::Locked { .. } => {}
+            }
+        }
+        for (score, question) in scores.iter_mut().zip(&self.questions) {
+            match &question.score {
+                Some(stored) => *score = stored.clone(),
+                None => {
+                    score.points =
+                        question
+                            .scoring
+                            .score(question.status, score.wrong_answers, score.queries)
+                }
+            }
+        }
+        scores
+    }
+
+    /// The points for all of the questions together, and the most that they could be worth.
+    pub fn total_score(&self) -> (f64, f64) {
+        self.scores()
+            .iter()
+            .fold((0.0, 0.0), |(points, max), score| {
+                (points + score.points, max + score.max_points)
+            })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::Utc;
+
+    use super::*;
+    use crate::session::{Answer, Question, Request, RequestLogEntry, ResponseResult, Session};
+
+    fn query(question: usize) -> Event {
+        Event::Request {
+            question,
+            request: Request {
+                server_ip: "198.41.0.4".to_owned(),
+                name: "example.com".to_owned(),
+                record_type: trust_dns_client::rr::RecordType::NS,
+            },
+            response: ResponseResult::QueryError {
+                err: "test".to_owned(),
+            },
+        }
+    }
+
+    fn answer(question: usize, status: AnswerStatus) -> Event {
+        Event::SubmitAnswer {
+            question,
+            answer: "?".to_owned(),
+            status,
+        }
+    }
+
+    #[test]
+    fn test_score() {
+        let scoring = Scoring {
+            points: 10.0,
+            wrong_answer_penalty: 2.0,
+            query_budget: Some(4),
+            over_budget_penalty: 1.0,
+            under_budget_bonus: 0.5,
+        };
+        assert_eq!(scoring.score(QuestionStatus::Correct, 0, 4), 10.0);
+        assert_eq!(scoring.score(QuestionStatus::Correct, 1, 6), 6.0);
+        assert_eq!(scoring.score(QuestionStatus::Correct, 0, 2), 11.0);
+        assert_eq!(scoring.score(QuestionStatus::Correct, 10, 4), 0.0);
+        assert_eq!(scoring.score(QuestionStatus::OutOfAttempts, 0, 0), 0.0);
+        assert_eq!(scoring.score(QuestionStatus::Open, 0, 0), 0.0);
+        assert_eq!(scoring.max_points(), 12.0);
+    }
+
+    #[test]
+    fn test_scores_from_log() {
+        let question = || Question::new("?".to_owned(), Answer::Preset { options: vec![] });
+        let mut session = Session::new(vec![question(), question()]);
+        session.questions[0].scoring.query_budget = Some(1);
+        session.questions[0].status = QuestionStatus::Correct;
+        session.questions[1].status = QuestionStatus::OutOfAttempts;
+        let events = [
+            query(0),
+            query(1),
+            query(0),
+            answer(0, AnswerStatus::Incorrect),
+            answer(0, AnswerStatus::Error),
+            answer(0, AnswerStatus::Correct),
+            // Made after the question was answered, so it does not count.
+            query(0),
+            answer(1, AnswerStatus::Incorrect),
+        ];
+        session.user_requests = events
+            .into_iter()
+            .map(|what| RequestLogEntry {
+                when: Utc::now(),
+                what,
+            })
+            .collect();
+
+        let scores = session.scores();
+        assert_eq!(scores[0].queries, 2);
+        assert_eq!(scores[0].wrong_answers, 1);
+        assert_eq!(scores[0].points, 7.5);
+        assert_eq!(scores[1].queries, 1);
+        assert_eq!(scores[1].points, 0.0);
+        assert_eq!(session.total_score(), (7.5, 20.0));
+    }
+
+    #[test]
+    fn test_stored_scores() {
+        let mut session = Session::new(vec![Question::new(
+            "?".to_owned(),
+            Answer::Preset { options: vec![] },
+        )]);
+        let log = |what| {
+            vec![RequestLogEntry {
+                when: Utc::now(),
+                what,
+            }]
+        };
+        let wrong = log(answer(0, AnswerStatus::Incorrect));
+        session.store_scores(&wrong);
+        assert_eq!(session.questions[0].score, None);
+        session.user_requests.extend(wrong);
+
+        session.questions[0].status = QuestionStatus::Correct;
+        session.store_scores(&log(answer(0, AnswerStatus::Correct)));
+        assert_eq!(session.scores()[0].points, 8.0);
+
+        // The teacher changes the scoring after the question was graded.
+        session.questions[0].scoring.points = 100.0;
+        assert_eq!(session.scores()[0].points, 8.0);
+        assert_eq!(session.total_score(), (8.0, 10.0));
+
+        session.reset(3);
+        assert_eq!(session.scores()[0].points, 0.0);
+    }
+}
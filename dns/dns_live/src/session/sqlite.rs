@@ -0,0 +1,345 @@
+use std::{
+    path::Path,
+    sync::{Arc, Mutex},
+};
+
+use async_trait::async_trait;
+use rusqlite::{params, Connection, OptionalExtension, Transaction};
+use serde_json::Value;
+
+use super::{
+    migrate, store::valid_keys, CreateError, RequestLogEntry, Session, SessionError, SessionKey,
+    SessionStore, StorageError, UpdateError, SCHEMA_VERSION,
+};
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS sessions (
+        key TEXT PRIMARY KEY,
+        -- The session as JSON, with an empty log
+        state TEXT NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS log_entries (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        session_key TEXT NOT NULL REFERENCES sessions(key) ON DELETE CASCADE,
+        -- A RequestLogEntry as JSON
+        entry TEXT NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS log_entries_by_session ON log_entries(session_key, id);
+";
+
+/// Stores sessions in an SQLite database.
+/// The log of a session is kept in its own table, one row per entry,
+/// so adding to it does not require rewriting the whole session.
+pub struct SqliteStore {
+    conn: Arc<Mutex<Connection>>,
+}
+
+impl SqliteStore {
+    pub fn open(path: &Path) -> rusqlite::Result<Self> {
+        Self::from_connection(Connection::open(path)?)
+    }
+
+    #[cfg(test)]
+    pub fn open_in_memory() -> rusqlite::Result<Self> {
+        Self::from_connection(Connection::open_in_memory()?)
+    }
+
+    fn from_connection(conn: Connection) -> rusqlite::Result<Self> {
+        conn.pragma_update(None, "foreign_keys", "ON")?;
+        conn.execute_batch(SCHEMA)?;
+        Ok(Self {
+            conn: Arc::new(Mutex::new(conn)),
+        })
+    }
+
+    /// Run a database operation in a transaction, off the async runtime.
+    /// The transaction is committed only if the operation succeeds.
+    async fn with_transaction<T, E, F>(&self, f: F) -> Result<T, E>
+    where
+        T: Send + 'static,
+        E: From<rusqlite::Error> + Send + 'static,
+        F: FnOnce(&Transaction) -> Result<T, E> + Send + 'static,
+    {
+        let conn = self.conn.clone();
+        tokio::task::spawn_blocking(move || {
+            let mut conn = conn.lock().unwrap();
+            let tx = conn.transaction()?;
+            // If the operation fails, the transaction is rolled back when it is dropped.
+            let result = f(&tx)?;
+            tx.commit()?;
+            Ok(result)
+        })
+        .await
+        .expect("session database task panicked")
+    }
+}
+
+/// The session without its log, as it is kept in the `sessions` table.
+fn state_json(session: &Session) -> Result<String, StorageError> {
+    let mut state = serde_json::to_value(session)?;
+    if let Value::Object(fields) = &mut state {
+        fields.insert("user_requests".to_owned(), Value::Array(vec![]));
+    }
+    Ok(serde_json::to_string(&state)?)
+}
+
+/// Replace the stored session and its whole log.
+fn replace(
+    tx: &Transaction,
+    key: &str,
+    state: &str,
+    log: &[RequestLogEntry],
+) -> Result<(), UpdateError> {
+    let updated = tx.execute(
+        "UPDATE sessions SET state = ?2 WHERE key = ?1",
+        params![key, state],
+    )?;
+    if updated == 0 {
+        return Err(UpdateError::NotFound);
+    }
+    tx.execute(
+        "DELETE FROM log_entries WHERE session_key = ?1",
+        params![key],
+    )?;
+    Ok(insert_entries(tx, key, log)?)
+}
+
+fn insert_entries(
+    tx: &Transaction,
+    key: &str,
+    entries: &[RequestLogEntry],
+) -> Result<(), StorageError> {
+    let mut insert =
+        tx.prepare_cached("INSERT INTO log_entries (session_key, entry) VALUES (?1, ?2)")?;
+    for entry in entries {
+        insert.execute(params![key, serde_json::to_string(entry)?])?;
+    }
+    Ok(())
+}
+
+#[async_trait]
+impl SessionStore for SqliteStore {
+    async fn list(&self) -> Result<Vec<SessionKey>, SessionError> {
+        self.with_transaction(|tx| {
+            let mut select = tx.prepare("SELECT key FROM sessions ORDER BY key")?;
+            let keys = select
+                .query_map([], |row| row.get(0))?
+                .collect::<rusqlite::Result<Vec<String>>>()?;
+            Ok(valid_keys(keys))
+        })
+        .await
+    }
+
+    async fn get(&self, key: &SessionKey) -> Result<Session, SessionError> {
+        let key = key.to_string();
+        self.with_transaction(move |tx| {
+            let state: Option<String> = tx
+                .query_row(
+                    "SELECT state FROM sessions WHERE key = ?1",
+                    params![key],
+                    |row| row.get(0),
+                )
+                .optional()?;
+            let mut document: Value = serde_json::from_str(&state.ok_or(SessionError::NotFound)?)?;
+            let mut select =
+                tx.prepare("SELECT entry FROM log_entries WHERE session_key = ?1 ORDER BY id")?;
+            let mut log = vec![];
+            for entry in select.query_map(params![key], |row| row.get::<_, String>(0))? {
+                log.push(serde_json::from_str::<Value>(&entry?)?);
+            }
+            // The log is put back into the session before it is upgraded,
+            // so that old entries are upgraded along with the rest.
+            if let Value::Object(fields) = &mut document {
+                fields.insert("user_requests".to_owned(), Value::Array(log));
+            }
+            let stored_version = migrate::version(&document)?;
+            let session = Session::from_document(document)?;
+            // New entries are appended without touching the old ones,
+            // so an upgraded session is written back whole.
+            if stored_version < SCHEMA_VERSION {
+                replace(tx, &key, &state_json(&session)?, &session.user_requests)?;
+            }
+            Ok(session)
+        })
+        .await
+    }
+
+    async fn create_session(
+        &self,
+        key: &SessionKey,
+        new_session: Session,
+    ) -> Result<(), CreateError> {
+        let key = key.to_string();
+        let state = state_json(&new_session)?;
+        let log = new_session.user_requests;
+        self.with_transaction(move |tx| {
+            let inserted = tx.execute(
+                "INSERT OR IGNORE INTO sessions (key, state) VALUES (?1, ?2)",
+                params![key, state],
+            )?;
+            if inserted == 0 {
+                return Err(CreateError::AlreadyExists);
+            }
+            Ok(insert_entries(tx, &key, &log)?)
+        })
+        .await
+    }
+
+    async fn update_session(
+        &self,
+        key: &SessionKey,
+        new_session: Session,
+    ) -> Result<(), UpdateError> {
+        let key = key.to_string();
+        let state = state_json(&new_session)?;
+        let log = new_session.user_requests;
+        self.with_transaction(move |tx| replace(tx, &key, &state, &log))
+            .await
+    }
+
+    async fn append(
+        &self,
+        key: &SessionKey,
+        session: Session,
+        entries: Vec<RequestLogEntry>,
+    ) -> Result<(), SessionError> {
+        let key = key.to_string();
+        let state = state_json(&session)?;
+        self.with_transaction(move |tx| {
+            let updated = tx.execute(
+                "UPDATE sessions SET state = ?2 WHERE key = ?1",
+                params![key, state],
+            )?;
+            if updated == 0 {
+                return Err(SessionError::NotFound);
+            }
+            Ok(insert_entries(tx, &key, &entries)?)
+        })
+        .await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::Utc;
+
+    use super::*;
+    use crate::session::{Answer, Event, OutputMode, Question};
+
+    fn key(key: &str) -> SessionKey {
+        key.parse().unwrap()
+    }
+
+    fn questions() -> Vec<Question> {
+        vec![Question::new(
+            "?".to_string(),
+            Answer::Preset {
+                options: vec!["yes".to_string()],
+            },
+        )]
+    }
+
+    fn switch(mode: OutputMode) -> Vec<RequestLogEntry> {
+        vec![RequestLogEntry {
+            when: Utc::now(),
+            what: Event::SwitchOutputMode { new_mode: mode },
+        }]
+    }
+
+    #[tokio::test]
+    async fn test_append_keeps_concurrent_entries() {
+        let store = SqliteStore::open_in_memory().unwrap();
+        store
+            .create_session(&key("a"), Session::new(questions()))
+            .await
+            .unwrap();
+        assert!(matches!(
+            store
+                .create_session(&key("a"), Session::new(questions()))
+                .await,
+            Err(CreateError::AlreadyExists)
+        ));
+
+        // Two requests read the session before either of them saves it.
+        let first = store.get(&key("a")).await.unwrap();
+        let second = store.get(&key("a")).await.unwrap();
+        store
+            .append(&key("a"), first, switch(OutputMode::Rust))
+            .await
+            .unwrap();
+        store
+            .append(&key("a"), second, switch(OutputMode::Classic))
+            .await
+            .unwrap();
+
+        let stored = store.get(&key("a")).await.unwrap();
+        assert_eq!(stored.user_requests.len(), 2);
+        assert_eq!(store.list().await.unwrap(), vec![key("a")]);
+
+        let mut reset = stored;
+        reset.reset(5);
+        store.update_session(&key("a"), reset).await.unwrap();
+        assert!(store.get(&key("a")).await.unwrap().user_requests.is_empty());
+        assert!(matches!(
+            store
+                .update_session(&key("b"), Session::new(questions()))
+                .await,
+            Err(UpdateError::NotFound)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_get_writes_back_upgraded_session() {
+        let store = SqliteStore::open_in_memory().unwrap();
+        let mut state: Value = serde_json::from_str(include_str!("fixtures/v3.json")).unwrap();
+        let Some(Value::Array(log)) = state
+            .as_object_mut()
+            .unwrap()
+            .insert("user_requests".to_owned(), Value::Array(vec![]))
+        else {
+            panic!("the fixture has a log");
+        };
+        {
+            let conn = store.conn.lock().unwrap();
+            conn.execute(
+                "INSERT INTO sessions (key, state) VALUES ('old', ?1)",
+                params![state.to_string()],
+            )
+            .unwrap();
+            for entry in &log {
+                conn.execute(
+                    "INSERT INTO log_entries (session_key, entry) VALUES ('old', ?1)",
+                    params![entry.to_string()],
+                )
+                .unwrap();
+            }
+        }
+
+        let upgraded = store.get(&key("old")).await.unwrap();
+        // Appending only adds the new entries, so the old ones must already be in the new format.
+        store
+            .append(&key("old"), upgraded, switch(OutputMode::Classic))
+            .await
+            .unwrap();
+        let conn = store.conn.lock().unwrap();
+        let state: String = conn
+            .query_row("SELECT state FROM sessions WHERE key = 'old'", [], |row| {
+                row.get(0)
+            })
+            .unwrap();
+        let state: Value = serde_json::from_str(&state).unwrap();
+        assert_eq!(migrate::version(&state).unwrap(), SCHEMA_VERSION);
+        let mut select = conn
+            .prepare("SELECT entry FROM log_entries ORDER BY id")
+            .unwrap();
+        let entries: Vec<String> = select
+            .query_map([], |row| row.get(0))
+            .unwrap()
+            .collect::<rusqlite::Result<_>>()
+            .unwrap();
+        assert_eq!(entries.len(), 4);
+        for entry in entries {
+            serde_json::from_str::<RequestLogEntry>(&entry).unwrap();
+        }
+    }
+}
@@ -0,0 +1,322 @@
+use std::{
+    io::ErrorKind,
+    path::{Path, PathBuf},
+};
+
+use async_trait::async_trait;
+use tokio::{
+    fs::OpenOptions,
+    io::{AsyncReadExt, AsyncWriteExt},
+};
+
+use super::{
+    CreateError, RequestLogEntry, Session, SessionError, SessionKey, StorageError, UpdateError,
+};
+
+/// Somewhere sessions are kept between requests.
+#[async_trait]
+pub trait SessionStore: Send + Sync {
+    /// Keys of all sessions, sorted.
+    async fn list(&self) -> Result<Vec<SessionKey>, SessionError>;
+
+    async fn get(&self, key: &SessionKey) -> Result<Session, SessionError>;
+
+    /// Store a new session, refusing to overwrite an existing one with the same key.
+    async fn create_session(
+        &self,
+        key: &SessionKey,
+        new_session: Session,
+    ) -> Result<(), CreateError>;
+
+    /// Replace an existing session entirely, including its log.
+    /// Does not create the session if it does not exist.
+    async fn update_session(
+        &self,
+        key: &SessionKey,
+        new_session: Session,
+    ) -> Result<(), UpdateError>;
+
+    /// Save the state of an existing session and add entries to the end of its log.
+    ///
+    /// The log of `session` itself is ignored: the stored log is kept,
+    /// so that entries appended by concurrent requests are not lost.
+    /// The rest of the state is replaced with that of `session`, so a caller that changes it
+    /// must hold the session's lock from [`SessionLocks`](super::SessionLocks)
+    /// from reading the session until saving it, or the changes of concurrent requests are lost.
+    /// A store may read the stored session to do this, so it can fail the same way as reading.
+    async fn append(
+        &self,
+        key: &SessionKey,
+        session: Session,
+        entries: Vec<RequestLogEntry>,
+    ) -> Result<(), SessionError>;
+}
+
+/// Stores every session as a JSON file in a directory, named after the session key.
+pub struct FileStore {
+    dir: PathBuf,
+}
+
+impl FileStore {
+    pub fn new(dir: &Path) -> Self {
+        Self {
+            dir: dir.to_path_buf(),
+        }
+    }
+
+    /// Write a session to a fresh temporary file next to where it will be stored,
+    /// and make sure it has reached the disk.
+    async fn write_temp(
+        &self,
+        key: &SessionKey,
+        session: &Session,
+    ) -> Result<PathBuf, StorageError> {
+        let suffix: u64 = rand::random();
+        let temp = self.dir.join(format!(".{key}.{suffix:016x}.tmp"));
+        let data = serde_json::to_vec_pretty(session)?;
+        let written = async {
+            let mut file = OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(&temp)
+                .await?;
+            file.write_all(&data).await?;
+            file.sync_all().await
+        }
+        .await;
+        if let Err(e) = written {
+            let _ = tokio::fs::remove_file(&temp).await;
+            return Err(e.into());
+        }
+        Ok(temp)
+    }
+
+    /// The file that the session with this key is stored in.
+    fn session_path(&self, key: &SessionKey) -> PathBuf {
+        let mut path = self.dir.clone();
+        path.push(key.as_str());
+        path.set_extension("json");
+        path
+    }
+}
+
+#[async_trait]
+impl SessionStore for FileStore {
+    async fn list(&self) -> Result<Vec<SessionKey>, SessionError> {
+        let mut entries = tokio::fs::read_dir(&self.dir).await?;
+        let mut keys = vec![];
+        while let Some(entry) = entries.next_entry().await? {
+            let path = entry.path();
+            if path.extension().is_some_and(|ext| ext == "json") {
+                if let Some(stem) = path.file_stem() {
+                    keys.push(stem.to_string_lossy().into_owned());
+                }
+            }
+        }
+        Ok(valid_keys(keys))
+    }
+
+    async fn get(&self, key: &SessionKey) -> Result<Session, SessionError> {
+        let path = self.session_path(key);
+        let mut file = match tokio::fs::File::open(path).await {
+            Ok(file) => file,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Err(SessionError::NotFound),
+            Err(e) => return Err(e.into()),
+        };
+        let mut data = String::new();
+        file.read_to_string(&mut data).await?;
+        Session::from_json(&data)
+    }
+
+    async fn create_session(
+        &self,
+        key: &SessionKey,
+        new_session: Session,
+    ) -> Result<(), CreateError> {
+        let path = self.session_path(key);
+        let temp = self.write_temp(key, &new_session).await?;
+        // Linking fails if the target exists, so an existing session is never overwritten,
+        // and the new file appears with all of its contents at once.
+        let linked = tokio::fs::hard_link(&temp, &path).await;
+        let _ = tokio::fs::remove_file(&temp).await;
+        match linked {
+            Ok(()) => Ok(()),
+            Err(e) if e.kind() == ErrorKind::AlreadyExists => Err(CreateError::AlreadyExists),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn update_session(
+        &self,
+        key: &SessionKey,
+        new_session: Session,
+    ) -> Result<(), UpdateError> {
+        let path = self.session_path(key);
+        match tokio::fs::metadata(&path).await {
+            Ok(_) => {}
+            Err(e) if e.kind() == ErrorKind::NotFound => return Err(UpdateError::NotFound),
+            Err(e) => return Err(e.into()),
+        }
+        let temp = self.write_temp(key, &new_session).await?;
+        // Renaming over the old file is atomic: readers see either the old or the new session,
+        // never a partially written one.
+        if let Err(e) = tokio::fs::rename(&temp, &path).await {
+            let _ = tokio::fs::remove_file(&temp).await;
+            return Err(e.into());
+        }
+        Ok(())
+    }
+
+    async fn append(
+        &self,
+        key: &SessionKey,
+        mut session: Session,
+        entries: Vec<RequestLogEntry>,
+    ) -> Result<(), SessionError> {
+        let stored = self.get(key).await?;
+        session.user_requests = stored.user_requests;
+        session.user_requests.extend(entries);
+        Ok(self.update_session(key, session).await?)
+    }
+}
+
+/// Sort the keys of stored sessions, skipping those that are not valid keys:
+/// such sessions could not be opened anyway.
+pub(super) fn valid_keys(keys: Vec<String>) -> Vec<SessionKey> {
+    let mut valid: Vec<SessionKey> = keys
+        .into_iter()
+        .filter_map(|key| match SessionKey::new(key.clone()) {
+            Ok(key) => Some(key),
+            Err(e) => {
+                tracing::warn!("Ignoring stored session {key:?}: {e}");
+                None
+            }
+        })
+        .collect();
+    valid.sort();
+    valid
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use chrono::Utc;
+
+    use super::*;
+    use crate::session::{Answer, Event, OutputMode, Question, SessionLocks};
+
+    fn key(key: &str) -> SessionKey {
+        key.parse().unwrap()
+    }
+
+    fn temp_store(name: &str) -> FileStore {
+        let dir = std::env::temp_dir().join(format!("dns_live_{name}_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        FileStore::new(&dir)
+    }
+
+    fn session() -> Session {
+        Session::new(vec![Question::new(
+            "?".to_string(),
+            Answer::Preset {
+                options: vec!["yes".to_string()],
+            },
+        )])
+    }
+
+    #[tokio::test]
+    async fn test_create_and_update() {
+        let store = temp_store("create");
+        assert!(matches!(
+            store.update_session(&key("a"), session()).await,
+            Err(UpdateError::NotFound)
+        ));
+        assert!(store.list().await.unwrap().is_empty());
+        store.create_session(&key("a"), session()).await.unwrap();
+        assert!(matches!(
+            store.create_session(&key("a"), session()).await,
+            Err(CreateError::AlreadyExists)
+        ));
+
+        // A shorter session must not leave the end of the longer one behind.
+        let mut long = session();
+        long.questions[0].text = "?".repeat(10000);
+        store.update_session(&key("a"), long).await.unwrap();
+        store.update_session(&key("a"), session()).await.unwrap();
+        assert_eq!(store.get(&key("a")).await.unwrap().questions[0].text, "?");
+        assert_eq!(store.list().await.unwrap(), vec![key("a")]);
+    }
+
+    #[tokio::test]
+    async fn test_read_errors() {
+        let store = temp_store("errors");
+        assert!(matches!(
+            store.get(&key("a")).await,
+            Err(SessionError::NotFound)
+        ));
+
+        // A teacher has made a typo while editing the file by hand.
+        let mut text = serde_json::to_string_pretty(&session()).unwrap();
+        text = text.replacen("\"can_answer\": true", "\"can_answer\": tru", 1);
+        std::fs::write(store.session_path(&key("a")), &text).unwrap();
+        let line = text.lines().position(|l| l.contains("tru")).unwrap() + 1;
+        match store.get(&key("a")).await {
+            Err(SessionError::Corrupt {
+                line: error_line, ..
+            }) => assert_eq!(error_line, line),
+            other => panic!("expected a corrupt session, got {:?}", other.err()),
+        }
+
+        std::fs::write(store.session_path(&key("a")), r#"{"schema_version": 1000}"#).unwrap();
+        assert!(matches!(
+            store.get(&key("a")).await,
+            Err(SessionError::VersionMismatch { found: 1000, .. })
+        ));
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_concurrent_requests() {
+        let store = Arc::new(temp_store("concurrent"));
+        let locks = Arc::new(SessionLocks::default());
+        store.create_session(&key("a"), session()).await.unwrap();
+
+        let mut tasks = vec![];
+        for i in 0..50 {
+            let writer_store = store.clone();
+            let locks = locks.clone();
+            tasks.push(tokio::spawn(async move {
+                let store = writer_store;
+                // What a request handler does: read, change, and save under the lock.
+                let _lock = locks.lock(&key("a")).await;
+                let mut session = store.get(&key("a")).await.expect("session is never torn");
+                let mode = if i % 2 == 0 {
+                    OutputMode::Rust
+                } else {
+                    OutputMode::Classic
+                };
+                session.current_output_mode = mode;
+                session.questions[0].answers_remaining += 1;
+                let entry = RequestLogEntry {
+                    when: Utc::now(),
+                    what: Event::SwitchOutputMode { new_mode: mode },
+                };
+                store.append(&key("a"), session, vec![entry]).await.unwrap();
+            }));
+            // Readers that do not take the lock must still always see a whole session.
+            let store = store.clone();
+            tasks.push(tokio::spawn(async move {
+                store.get(&key("a")).await.expect("session is never torn");
+            }));
+        }
+        for task in tasks {
+            task.await.unwrap();
+        }
+
+        let stored = store.get(&key("a")).await.unwrap();
+        assert_eq!(stored.user_requests.len(), 50);
+        assert_eq!(stored.questions[0].answers_remaining, 55);
+        assert_eq!(store.list().await.unwrap(), vec![key("a")]);
+    }
+}
@@ -1,28 +1,108 @@
-use axum::{response::{Html, Redirect}, routing::get, Router, Form};
+use std::{net::SocketAddr, sync::Arc};
+
+use axum::{http::StatusCode, response::{Html, Redirect}, routing::{get, post}, Router, Form};
 use minijinja::context;
 
+use socket2::{Domain, Protocol, Socket, Type};
+use tokio::task::JoinSet;
+
+use crate::{
+    config::Config,
+    session::{SessionKey, SessionLocks, SessionStore},
+    world::{World, WorldCache, WorldData},
+};
+
+use self::auth::CookieSigner;
 use self::templates::{env, SessionKeyRequest};
 
-mod templates;
+mod admin;
+mod auth;
+pub mod templates;
 mod ui;
 
-pub async fn web_main() -> ! {
-    let app = get_router();
+/// State shared by all request handlers.
+#[derive(Clone)]
+pub struct AppState {
+    pub config: Arc<Config>,
+    pub sessions: Arc<dyn SessionStore>,
+    pub session_locks: Arc<SessionLocks>,
+    pub worlds: Arc<WorldCache>,
+    pub cookies: Arc<CookieSigner>,
+    /// Held while checking a session's password, so that guesses cannot be made in parallel.
+    /// These are separate from the session locks, so that guessing does not hold up the student.
+    pub login_locks: Arc<SessionLocks>,
+    /// Held while checking the admin password, for the same reason.
+    pub admin_login: Arc<tokio::sync::Mutex<()>>,
+}
 
-    let server =
-        axum::Server::bind(&"0.0.0.0:5000".parse().unwrap()).serve(app.into_make_service());
-    if let Err(e) = server.await {
-        panic!("Server error: {e:?}");
-    } else {
-        unreachable!("Server stopped on its own?!");
+impl AppState {
+    /// The world that the session with this key lives in.
+    pub async fn world_for(&self, key: &SessionKey) -> Arc<World> {
+        self.worlds.get(key.as_str()).await
     }
 }
 
-fn get_router() -> Router {
+pub async fn web_main(
+    config: Config,
+    sessions: Arc<dyn SessionStore>,
+    world_data: WorldData,
+    secret: Vec<u8>,
+) -> ! {
+    let listen = config.listen.clone();
+    let worlds = WorldCache::new(world_data, secret.clone(), config.world_cache_size);
+    let app = get_router().with_state(AppState {
+        config: Arc::new(config),
+        sessions,
+        session_locks: Arc::default(),
+        cookies: Arc::new(CookieSigner::new(&secret)),
+        login_locks: Arc::default(),
+        admin_login: Arc::default(),
+        worlds: Arc::new(worlds),
+    });
+
+    let mut servers = JoinSet::new();
+    for addr in listen {
+        let listener = bind(addr).unwrap_or_else(|e| panic!("Could not listen on {addr}: {e}"));
+        let server = axum::Server::from_tcp(listener)
+            .unwrap_or_else(|e| panic!("Could not listen on {addr}: {e}"))
+            .serve(app.clone().into_make_service());
+        tracing::info!("Listening on {addr}");
+        servers.spawn(server);
+    }
+    match servers.join_next().await {
+        Some(Ok(Err(e))) => panic!("Server error: {e:?}"),
+        Some(Err(e)) => panic!("Server task failed: {e:?}"),
+        Some(Ok(Ok(()))) => unreachable!("Server stopped on its own?!"),
+        None => panic!("No addresses to listen on"),
+    }
+}
+
+/// Open a listening socket.
+/// IPv6 sockets are made IPv6-only, so that they can coexist with an IPv4 socket on the same port.
+fn bind(addr: SocketAddr) -> std::io::Result<std::net::TcpListener> {
+    let socket = Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::TCP))?;
+    if addr.is_ipv6() {
+        socket.set_only_v6(true)?;
+    }
+    socket.set_reuse_address(true)?;
+    socket.bind(&addr.into())?;
+    socket.listen(1024)?;
+    Ok(socket.into())
+}
+
+fn get_router() -> Router<AppState> {
     Router::new()
         .route("/", get(home).post(route_to_session))
         .fallback(not_found)
+        .route("/admin", get(admin::sessions))
+        .route("/admin/login", get(admin::login_form).post(admin::login))
+        .route("/admin/logout", post(admin::logout))
+        .route("/admin/grades", get(admin::grades))
+        .route("/admin/:session", get(admin::session))
+        .route("/admin/:session/trace", get(admin::trace))
         .route("/:session", get(ui::session_get).post(ui::session_post))
+        .route("/:session/login", post(ui::login))
+        .route("/:session/logout", post(ui::logout))
 }
 
 async fn home() -> Html<String> {
@@ -37,8 +117,20 @@ async fn home() -> Html<String> {
 
 async fn route_to_session(
     Form(request): Form<SessionKeyRequest>,
-) -> Redirect {
-    Redirect::to(&("/".to_string() + &request.key))
+) -> Result<Redirect, (StatusCode, Html<String>)> {
+    // Only redirect to keys that are valid, so that the key cannot point anywhere else.
+    match SessionKey::new(request.key.trim().to_owned()) {
+        Ok(key) => Ok(Redirect::to(&format!("/{key}"))),
+        Err(_) => {
+            let env = env();
+            let html = env
+                .get_template("home")
+                .unwrap()
+                .render(context!(invalid_key => true))
+                .unwrap();
+            Err((StatusCode::BAD_REQUEST, Html(html)))
+        }
+    }
 }
 
 
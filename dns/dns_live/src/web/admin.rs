@@ -0,0 +1,337 @@
+//! Pages for the teacher: an overview of all sessions, and a read-only view of each one.
+
+use axum::{
+    async_trait,
+    extract::{FromRequestParts, Path, Query, State},
+    http::{header, request::Parts, HeaderName, HeaderValue, StatusCode},
+    response::{Html, IntoResponse, Redirect, Response},
+    Form,
+};
+use chrono::{DateTime, Utc};
+use minijinja::context;
+use serde::Serialize;
+
+use super::auth::{check_password, clear_cookie, login_duration, ADMIN_COOKIE};
+use super::templates::{env, ExportRequest, LoginRequest, SessionView, TraceRequest};
+use super::ui::{invalid_key_page, session_error_page};
+use super::AppState;
+use crate::analysis::{by_entry, shortcut_count};
+use crate::export::{format_rows, format_trace, grade_rows};
+use crate::output::{message_views, revealed_path};
+use crate::questions::hint;
+use crate::session::{
+    LockReason, QuestionStatus, Session, SessionError, SessionKey, SessionMode, Window,
+};
+use crate::world::World;
+
+/// The value of the admin cookie.
+const ADMIN: &str = "admin";
+
+/// Proof that a request comes from the teacher.
+/// Extracting it fails with a redirect to the login page unless the browser has logged in.
+pub struct Admin;
+
+#[async_trait]
+impl FromRequestParts<AppState> for Admin {
+    type Rejection = Response;
+
+    async fn from_request_parts(parts: &mut Parts, state: &AppState) -> Result<Self, Response> {
+        let Some(hash) = &state.config.admin_password_hash else {
+            return Err(admin_disabled_page().into_response());
+        };
+        if state
+            .cookies
+            .logged_in(&parts.headers, ADMIN_COOKIE, ADMIN, hash, Utc::now())
+        {
+            Ok(Admin)
+        } else {
+            Err(Redirect::to("/admin/login").into_response())
+        }
+    }
+}
+
+/// The page to show instead of the teacher's pages when there is no admin password.
+fn admin_disabled_page() -> (StatusCode, Html<String>) {
+    let html = env()
+        .get_template("admindisabled")
+        .unwrap()
+        .render(context!())
+        .unwrap();
+    (StatusCode::NOT_FOUND, Html(html))
+}
+
+fn login_page(wrong_password: bool) -> (StatusCode, Html<String>) {
+    let html = env()
+        .get_template("adminlogin")
+        .unwrap()
+        .render(context!(wrong_password => wrong_password))
+        .unwrap();
+    let status = if wrong_password {
+        StatusCode::UNAUTHORIZED
+    } else {
+        StatusCode::OK
+    };
+    (status, Html(html))
+}
+
+pub async fn login_form(State(state): State<AppState>) -> (StatusCode, Html<String>) {
+    if state.config.admin_password_hash.is_none() {
+        return admin_disabled_page();
+    }
+    login_page(false)
+}
+
+pub async fn login(
+    State(state): State<AppState>,
+    Form(request): Form<LoginRequest>,
+) -> Result<([(HeaderName, HeaderValue); 1], Redirect), (StatusCode, Html<String>)> {
+    let Some(hash) = state.config.admin_password_hash.clone() else {
+        return Err(admin_disabled_page());
+    };
+    let _lock = state.admin_login.lock().await;
+    if !check_password(hash.clone(), request.password).await {
+        tracing::warn!("Wrong admin password");
+        return Err(login_page(true));
+    }
+    let cookie = state
+        .cookies
+        .set_login(ADMIN_COOKIE, ADMIN, &hash, Utc::now() + login_duration());
+    Ok(([(header::SET_COOKIE, cookie)], Redirect::to("/admin")))
+}
+
+pub async fn logout() -> ([(HeaderName, HeaderValue); 1], Redirect) {
+    (
+        [(header::SET_COOKIE, clear_cookie(ADMIN_COOKIE))],
+        Redirect::to("/"),
+    )
+}
+
+/// A line of the session overview.
+#[derive(Serialize)]
+struct SessionRow {
+    key: SessionKey,
+    /// Why the session could not be read, if it could not.
+    error: Option<&'static str>,
+    has_password: bool,
+    first_question: String,
+    questions: usize,
+    correct: usize,
+    attempts_left: usize,
+    status: &'static str,
+    lock_reason: Option<LockReason>,
+    queries: usize,
+    /// Queries to servers the student could not have known about.
+    shortcuts: usize,
+    last_activity: Option<DateTime<Utc>>,
+    score: f64,
+    max_score: f64,
+}
+
+impl SessionRow {
+    fn new(key: SessionKey, session: &Session, world: &World, now: DateTime<Utc>) -> Self {
+        let status = if !session.can_answer {
+            "locked"
+        } else {
+            match session.window(now) {
+                Window::NotOpenYet { .. } => "not open yet",
+                Window::Open { .. } if session.started_at.is_some() => "in progress",
+                Window::Open { .. } => "open",
+                // It gets locked once someone looks at it.
+                Window::Over(_) => "over",
+            }
+        };
+        let (score, max_score) = session.total_score();
+        Self {
+            key,
+            error: None,
+            has_password: session.password_hash.is_some(),
+            first_question: session
+                .questions
+                .first()
+                .map(|question| question.text.clone())
+                .unwrap_or_default(),
+            questions: session.questions.len(),
+            correct: session
+                .questions
+                .iter()
+                .filter(|question| question.status == QuestionStatus::Correct)
+                .count(),
+            attempts_left: session
+                .questions
+                .iter()
+                .filter(|question| question.status == QuestionStatus::Open)
+                .map(|question| question.answers_remaining)
+                .sum(),
+            status,
+            lock_reason: session.lock_reason(),
+            queries: session.query_count(),
+            shortcuts: shortcut_count(world, session),
+            last_activity: session.user_requests.last().map(|entry| entry.when),
+            score,
+            max_score,
+        }
+    }
+
+    /// A line for a session that could not be read.
+    /// The error itself is only logged, as it may quote anything from the stored session.
+    fn unreadable(key: SessionKey, error: &SessionError) -> Self {
+        tracing::error!("Could not read session {key}: {error}");
+        Self {
+            key,
+            error: Some(match error {
+                SessionError::NotFound => "deleted",
+                SessionError::Corrupt { .. } => "broken",
+                SessionError::VersionMismatch { .. } => "unsupported version",
+                SessionError::Io(_) => "unavailable",
+            }),
+            has_password: false,
+            first_question: String::new(),
+            questions: 0,
+            correct: 0,
+            attempts_left: 0,
+            status: "",
+            lock_reason: None,
+            queries: 0,
+            shortcuts: 0,
+            last_activity: None,
+            score: 0.0,
+            max_score: 0.0,
+        }
+    }
+}
+
+pub async fn sessions(_: Admin, State(state): State<AppState>) -> (StatusCode, Html<String>) {
+    let env = env();
+    let keys = match state.sessions.list().await {
+        Ok(keys) => keys,
+        Err(e) => {
+            tracing::error!("Could not list the sessions: {e}");
+            let html = env
+                .get_template("sessionunavailable")
+                .unwrap()
+                .render(context!(key => "admin"))
+                .unwrap();
+            return (StatusCode::INTERNAL_SERVER_ERROR, Html(html));
+        }
+    };
+    let now = Utc::now();
+    let mut rows = vec![];
+    for key in keys {
+        rows.push(match state.sessions.get(&key).await {
+            Ok(session) => {
+                let world = state.world_for(&key).await;
+                SessionRow::new(key, &session, &world, now)
+            }
+            Err(error) => SessionRow::unreadable(key, &error),
+        });
+    }
+    let html = env
+        .get_template("adminsessions")
+        .unwrap()
+        .render(context!(rows => rows))
+        .unwrap();
+    (StatusCode::OK, Html(html))
+}
+
+/// The grades of all sessions, as a file to download.
+pub async fn grades(
+    _: Admin,
+    State(state): State<AppState>,
+    Query(request): Query<ExportRequest>,
+) -> Response {
+    let rows = match grade_rows(state.sessions.as_ref(), &state.worlds).await {
+        Ok(rows) => rows,
+        Err(e) => {
+            tracing::error!("Could not list the sessions: {e}");
+            let html = env()
+                .get_template("sessionunavailable")
+                .unwrap()
+                .render(context!(key => "admin"))
+                .unwrap();
+            return (StatusCode::INTERNAL_SERVER_ERROR, Html(html)).into_response();
+        }
+    };
+    let format = request.format;
+    let disposition = format!("attachment; filename=\"grades.{}\"", format.extension());
+    (
+        [
+            (header::CONTENT_TYPE, format.content_type().to_string()),
+            (header::CONTENT_DISPOSITION, disposition),
+        ],
+        format_rows(&rows, format),
+    )
+        .into_response()
+}
+
+/// The student's page, as it is now, without anything that would change it.
+pub async fn session(
+    _: Admin,
+    State(state): State<AppState>,
+    Path(key): Path<String>,
+    Query(view): Query<SessionView>,
+) -> (StatusCode, Html<String>) {
+    let env = env();
+    let Ok(key) = SessionKey::new(key) else {
+        return invalid_key_page(&env);
+    };
+    let mut session = match state.sessions.get(&key).await {
+        Ok(session) => session,
+        Err(error) => return session_error_page(&env, &key, &error),
+    };
+    session.render_responses();
+    let current = session.current_question(view.q);
+    let world = state.world_for(&key).await;
+    let html = env
+        .get_template("session")
+        .unwrap()
+        .render(context!(
+            read_only => true,
+            key => key,
+            current => current,
+            question => session.questions.get(current),
+            hint => session.questions.get(current)
+                .filter(|_| session.mode == SessionMode::Practice)
+                .map(|question| hint(&question.answer)),
+            path => revealed_path(&world, &session, current),
+            scores => session.scores(),
+            can_act => false,
+            lock_reason => session.lock_reason(),
+            messages => message_views(&session),
+            analysis => by_entry(&world, &session),
+            session => session,
+            root_servers => world.root_server_addresses(),
+        ))
+        .unwrap();
+    (StatusCode::OK, Html(html))
+}
+
+/// Everything that happened in a session, as a file to download.
+pub async fn trace(
+    _: Admin,
+    State(state): State<AppState>,
+    Path(key): Path<String>,
+    Query(request): Query<TraceRequest>,
+) -> Response {
+    let env = env();
+    let Ok(key) = SessionKey::new(key) else {
+        return invalid_key_page(&env).into_response();
+    };
+    let session = match state.sessions.get(&key).await {
+        Ok(session) => session,
+        Err(error) => return session_error_page(&env, &key, &error).into_response(),
+    };
+    let world = state.world_for(&key).await;
+    let format = request.format;
+    let disposition = format!(
+        "attachment; filename=\"{key}-trace.{}\"",
+        format.extension()
+    );
+    (
+        [
+            (header::CONTENT_TYPE, format.content_type().to_string()),
+            (header::CONTENT_DISPOSITION, disposition),
+        ],
+        format_trace(&key, &session, &world, format, Utc::now()),
+    )
+        .into_response()
+}
@@ -0,0 +1,226 @@
+//! Logging in: checking passwords, and signed cookies that remember who a browser has logged in as.
+//!
+//! A cookie holds its value, when it expires, and an HMAC of both,
+//! so that it can be checked without storing anything on the server.
+//! A login cookie also holds a fingerprint of the password hash it was checked against,
+//! so changing or removing the password logs out every browser that used the old one.
+
+use axum::http::{header, HeaderMap, HeaderValue};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use hmac::{Hmac, Mac};
+use rand::RngCore;
+use sha2::{Digest, Sha256};
+
+use crate::password::verify_password;
+
+/// The cookie that binds a browser to a student's session.
+pub const SESSION_COOKIE: &str = "dns_live_session";
+/// The cookie that shows that the browser belongs to the teacher.
+pub const ADMIN_COOKIE: &str = "dns_live_admin";
+
+pub struct CookieSigner {
+    key: [u8; 32],
+}
+
+impl CookieSigner {
+    /// A signer with a key derived from the server's secret,
+    /// so that cookies stay valid when the server is restarted.
+    /// Without a secret, the key is random, and everyone has to log in again after a restart.
+    pub fn new(secret: &[u8]) -> Self {
+        let mut key = [0; 32];
+        if secret.is_empty() {
+            tracing::warn!("There is no secret, so logins will not survive a restart");
+            rand::thread_rng().fill_bytes(&mut key);
+        } else {
+            let mut hasher = Sha256::new();
+            hasher.update(b"dns_live cookies\0");
+            hasher.update(secret);
+            key = hasher.finalize().into();
+        }
+        Self { key }
+    }
+
+    fn mac(&self, name: &str, payload: &str) -> Hmac<Sha256> {
+        let mut mac =
+            Hmac::<Sha256>::new_from_slice(&self.key).expect("HMAC takes keys of any size");
+        // The name is included, so that one kind of cookie cannot be passed off as another.
+        mac.update(name.as_bytes());
+        mac.update(b"\0");
+        mac.update(payload.as_bytes());
+        mac
+    }
+
+    /// A `Set-Cookie` header value for a cookie that is valid until `expires`.
+    /// The value must not contain `;` or whitespace.
+    pub fn set_cookie(&self, name: &str, value: &str, expires: DateTime<Utc>) -> HeaderValue {
+        let payload = format!("{value}|{}", expires.timestamp());
+        let signature = URL_SAFE_NO_PAD.encode(self.mac(name, &payload).finalize().into_bytes());
+        let max_age = (expires - Utc::now()).num_seconds().max(0);
+        HeaderValue::from_str(&format!(
+            "{name}={payload}|{signature}; Max-Age={max_age}; Path=/; HttpOnly; SameSite=Lax"
+        ))
+        .expect("cookie values are ASCII")
+    }
+
+    /// The value of the cookie with this name, if it has a valid signature and has not expired.
+    pub fn get(&self, headers: &HeaderMap, name: &str, now: DateTime<Utc>) -> Option<String> {
+        let cookie = find_cookie(headers, name)?;
+        let (payload, signature) = cookie.rsplit_once('|')?;
+        let signature = URL_SAFE_NO_PAD.decode(signature).ok()?;
+        self.mac(name, payload).verify_slice(&signature).ok()?;
+        let (value, expires) = payload.rsplit_once('|')?;
+        let expires = Utc.timestamp_opt(expires.parse().ok()?, 0).single()?;
+        (now < expires).then(|| value.to_owned())
+    }
+
+    /// A `Set-Cookie` header value that remembers that the browser has logged in as `who`
+    /// with the password that has this hash.
+    pub fn set_login(
+        &self,
+        name: &str,
+        who: &str,
+        password_hash: &str,
+        expires: DateTime<Utc>,
+    ) -> HeaderValue {
+        self.set_cookie(name, &login_value(who, password_hash), expires)
+    }
+
+    /// Whether the browser has logged in as `who` with the password that has this hash now.
+    pub fn logged_in(
+        &self,
+        headers: &HeaderMap,
+        name: &str,
+        who: &str,
+        password_hash: &str,
+        now: DateTime<Utc>,
+    ) -> bool {
+        self.get(headers, name, now)
+            .is_some_and(|value| value == login_value(who, password_hash))
+    }
+}
+
+/// The value of a login cookie: who logged in, and a fingerprint of the password hash.
+fn login_value(who: &str, password_hash: &str) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(b"dns_live password\0");
+    hasher.update(password_hash.as_bytes());
+    let fingerprint = URL_SAFE_NO_PAD.encode(&hasher.finalize()[..12]);
+    format!("{who}|{fingerprint}")
+}
+
+/// A `Set-Cookie` header value that removes the cookie.
+pub fn clear_cookie(name: &str) -> HeaderValue {
+    HeaderValue::from_str(&format!(
+        "{name}=; Max-Age=0; Path=/; HttpOnly; SameSite=Lax"
+    ))
+    .expect("cookie names are ASCII")
+}
+
+/// How long a login lasts.
+pub fn login_duration() -> Duration {
+    Duration::hours(12)
+}
+
+/// How long to wait before answering that a password is wrong, to slow down guessing.
+const WRONG_PASSWORD_DELAY: std::time::Duration = std::time::Duration::from_secs(1);
+
+/// Whether the password matches the hash.
+/// Hashing takes a while on purpose, so it is done off the async runtime.
+pub async fn check_password(hash: String, password: String) -> bool {
+    let matches = tokio::task::spawn_blocking(move || verify_password(&hash, &password))
+        .await
+        .expect("password check panicked");
+    if !matches {
+        tokio::time::sleep(WRONG_PASSWORD_DELAY).await;
+    }
+    matches
+}
+
+/// The raw value of a cookie sent by the browser.
+fn find_cookie<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers
+        .get_all(header::COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(';'))
+        .filter_map(|pair| pair.trim().split_once('='))
+        .find(|(key, _)| *key == name)
+        .map(|(_, value)| value)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// The headers a browser would send back after receiving this `Set-Cookie`.
+    fn sent_back(set_cookie: &HeaderValue) -> HeaderMap {
+        let cookie = set_cookie.to_str().unwrap().split(';').next().unwrap();
+        let mut headers = HeaderMap::new();
+        headers.insert(header::COOKIE, HeaderValue::from_str(cookie).unwrap());
+        headers
+    }
+
+    #[test]
+    fn test_signed_cookies() {
+        let signer = CookieSigner::new(b"secret");
+        let now = Utc::now();
+        let set = signer.set_cookie(SESSION_COOKIE, "example1", now + Duration::hours(1));
+        let headers = sent_back(&set);
+        assert_eq!(
+            signer.get(&headers, SESSION_COOKIE, now).as_deref(),
+            Some("example1")
+        );
+        // Expired.
+        assert_eq!(
+            signer.get(&headers, SESSION_COOKIE, now + Duration::hours(2)),
+            None
+        );
+        // Signed with another key.
+        assert_eq!(
+            CookieSigner::new(b"other").get(&headers, SESSION_COOKIE, now),
+            None
+        );
+        // Under another name.
+        let renamed = set
+            .to_str()
+            .unwrap()
+            .replacen(SESSION_COOKIE, ADMIN_COOKIE, 1);
+        assert_eq!(
+            signer.get(
+                &sent_back(&HeaderValue::from_str(&renamed).unwrap()),
+                ADMIN_COOKIE,
+                now
+            ),
+            None
+        );
+        // With the value changed.
+        let forged = set.to_str().unwrap().replacen("example1", "example2", 1);
+        assert_eq!(
+            signer.get(
+                &sent_back(&HeaderValue::from_str(&forged).unwrap()),
+                SESSION_COOKIE,
+                now
+            ),
+            None
+        );
+    }
+
+    #[test]
+    fn test_login_cookies() {
+        let signer = CookieSigner::new(b"secret");
+        let now = Utc::now();
+        let set = signer.set_login(
+            SESSION_COOKIE,
+            "example1",
+            "hash1",
+            now + Duration::hours(1),
+        );
+        let headers = sent_back(&set);
+        assert!(signer.logged_in(&headers, SESSION_COOKIE, "example1", "hash1", now));
+        assert!(!signer.logged_in(&headers, SESSION_COOKIE, "example2", "hash1", now));
+        // The password was changed or removed after the browser logged in.
+        assert!(!signer.logged_in(&headers, SESSION_COOKIE, "example1", "hash2", now));
+        assert!(!signer.logged_in(&headers, SESSION_COOKIE, "example1", "", now));
+    }
+}
@@ -1,19 +1,37 @@
-use minijinja::Environment;
+use minijinja::{AutoEscape, Environment};
 use serde::Deserialize;
 use trust_dns_client::rr::RecordType;
 
+use crate::export::{ExportFormat, TraceFormat};
 use crate::session::OutputMode;
 
 pub fn env() -> Environment<'static> {
     let mut env = Environment::new();
+    // Sessions contain whatever the students typed in, and teachers look at them too.
+    env.set_auto_escape_callback(|_| AutoEscape::Html);
     env.add_template("theme", THEME).unwrap();
+    env.add_template("trace", TRACE).unwrap();
+    env.add_template("lockreason", LOCK_REASON).unwrap();
+    env.add_template("dnsmessage", DNS_MESSAGE).unwrap();
+    env.add_template("questionstatus", QUESTION_STATUS).unwrap();
     env.add_template("notfound", NOT_FOUND).unwrap();
     env.add_template("sessionnotfound", SESSION_NOT_FOUND)
         .unwrap();
+    env.add_template("invalidkey", INVALID_KEY).unwrap();
+    env.add_template("sessioncorrupt", SESSION_CORRUPT).unwrap();
+    env.add_template("sessionversion", SESSION_VERSION).unwrap();
+    env.add_template("sessionunavailable", SESSION_UNAVAILABLE).unwrap();
     env.add_template("session", SESSION).unwrap();
     env.add_template("errorsaving", ERROR_SAVING).unwrap();
-    env.add_template("noattempts", NO_ATTEMPTS).unwrap();
+    env.add_template("locked", LOCKED).unwrap();
+    env.add_template("questionclosed", QUESTION_CLOSED).unwrap();
+    env.add_template("modeunavailable", MODE_UNAVAILABLE).unwrap();
+    env.add_template("notopen", NOT_OPEN).unwrap();
     env.add_template("home", HOME).unwrap();
+    env.add_template("sessionlogin", SESSION_LOGIN).unwrap();
+    env.add_template("adminlogin", ADMIN_LOGIN).unwrap();
+    env.add_template("admindisabled", ADMIN_DISABLED).unwrap();
+    env.add_template("adminsessions", ADMIN_SESSIONS).unwrap();
 
     env
 }
@@ -60,45 +78,122 @@ const SESSION_NOT_FOUND: &str = r#"
 {% endblock %}
 "#;
 
+const INVALID_KEY: &str = r#"
+{% extends "notfound" %}
+{% block not_found_what %}
+<p>This is not a valid session key.
+Session keys consist of latin letters, digits, <code>-</code> and <code>_</code>.</p>
+{% endblock %}
+"#;
+
+const SESSION_CORRUPT: &str = r#"
+{% extends "theme" %}
+{% block head %}
+<title>Broken session</title>
+{% endblock %}
+{% block body %}
+<div class="px-4 py-5 text-center">
+    <h1 class="display-2 fw-bold">Broken session</h1>
+    <p>The session <code>{{ key }}</code> exists, but its file could not be read.
+    Please tell your teacher about this.</p>
+    <p>{% if line %}Error at line {{ line }}, column {{ column }}{% else %}Error{% endif %}: <code>{{ message }}</code></p>
+    <a href="/" class="btn btn-primary">Return to home</a>
+</div>
+{% endblock %}
+"#;
+
+const SESSION_VERSION: &str = r#"
+{% extends "theme" %}
+{% block head %}
+<title>Unsupported session</title>
+{% endblock %}
+{% block body %}
+<div class="px-4 py-5 text-center">
+    <h1 class="display-2 fw-bold">Unsupported session</h1>
+    <p>The session <code>{{ key }}</code> is stored in format version {{ found }},
+    but this server only understands version {{ supported }}.
+    Please tell your teacher about this.</p>
+    <a href="/" class="btn btn-primary">Return to home</a>
+</div>
+{% endblock %}
+"#;
+
+const SESSION_UNAVAILABLE: &str = r#"
+{% extends "theme" %}
+{% block head %}
+<title>Server error</title>
+{% endblock %}
+{% block body %}
+<div class="px-4 py-5 text-center">
+    <h1 class="display-2 fw-bold">Internal error</h1>
+    <p>There was an error while loading your session. Please try again later.</p>
+    <a href="/{{ key }}" class="btn btn-primary">Try again</a>
+</div>
+{% endblock %}
+"#;
+
 const SESSION: &str = r#"
 {% extends "theme" %}
 {% block head %}
 <title>Session</title>
 {% endblock %}
 {% block body %}
+{% if read_only %}
+<h1>Session <code>{{ key }}</code></h1>
+<div class="alert alert-secondary">This is how the session looks to the student right now; nothing can be changed here.
+<a href="/admin">Back to all sessions</a>.
+Download the history: <a href="/admin/{{ key }}/trace?format=html">report (HTML)</a>, <a href="/admin/{{ key }}/trace?format=jsonl">trace (JSONL)</a>.</div>
+{% else %}
 <h1>Session</h1>
+{% if has_password %}
+<form method=POST action="/{{ key }}/logout"><input type=submit class="btn btn-outline-secondary" value="Log out"/></form>
+{% endif %}
+{% endif %}
+{% if session.mode == "Practice" %}
+<div class="alert alert-info">This is a practice session: answers are not limited, every question has a hint,
+responses are explained, and the way to the answer of a question is shown once it is answered.</div>
+{% endif %}
 <p>Current output mode: {{ session.current_output_mode }}</p>
+{% if opens_in is not none %}
+<div class="alert alert-info">
+    This session opens in <span class="countdown" data-seconds="{{ opens_in }}"></span>.
+    {% if session.duration %}You will have {{ session.duration }} minutes from when you open it.{% endif %}
+</div>
+{% elif time_left is not none %}
+<div class="alert alert-warning">Time left: <span class="countdown" data-seconds="{{ time_left }}"></span></div>
+{% endif %}
 
 <div class="card my-3 border-info">
-    <h5 class="card-header">IANA Authoritative Root Servers</h5>
+    <h5 class="card-header">Root Servers</h5>
     <ul class="d-flex flex-wrap card-body">
-        <li class="badge rounded-pill text-bg-secondary m-1">198.41.0.4</li>
-        <li class="badge rounded-pill text-bg-secondary m-1">199.9.14.201</li>
-        <li class="badge rounded-pill text-bg-secondary m-1">192.33.4.12</li>
-        <li class="badge rounded-pill text-bg-secondary m-1">199.7.91.13</li>
-        <li class="badge rounded-pill text-bg-secondary m-1">192.203.230.10</li>
-        <li class="badge rounded-pill text-bg-secondary m-1">192.5.5.241</li>
-        <li class="badge rounded-pill text-bg-secondary m-1">192.112.36.4</li>
-        <li class="badge rounded-pill text-bg-secondary m-1">198.97.190.53</li>
-        <li class="badge rounded-pill text-bg-secondary m-1">192.36.148.17</li>
-        <li class="badge rounded-pill text-bg-secondary m-1">192.58.128.30</li>
-        <li class="badge rounded-pill text-bg-secondary m-1">193.0.14.129</li>
-        <li class="badge rounded-pill text-bg-secondary m-1">199.7.83.42</li>
-        <li class="badge rounded-pill text-bg-secondary m-1">202.12.27.33</li>
+        {% for addr in root_servers %}
+        <li class="badge rounded-pill text-bg-secondary m-1">{{ addr }}</li>
+        {% endfor %}
     </ul>
 </div>
 
 {% for item in session.user_requests %}
     {% if item.what.type == "Request" %}
     <div class="card my-3 {% if item.what.response.type != "Ok" %}border-danger{% else %}border-success{% endif %}">
-        <h5 class="card-header">{{ item.when }}</h5>
+        <h5 class="card-header">{{ item.when }}
+            {% if analysis is defined and analysis[loop.index0] %}{% with query = analysis[loop.index0] %}
+            <span class="badge text-bg-secondary">{{ query.label }}</span>
+            {% if query.shortcut %}<span class="badge text-bg-danger" title="The student had not been told about this server yet">shortcut</span>{% endif %}
+            {% endwith %}{% endif %}
+        </h5>
         <div class="card-body">
             <ul class="list-group list-group-flush">
             <li class="list-group-item">
                 Question: <code>IN {{item.what.request.record_type}} {{item.what.request.name}}</code> &rarr; <code>{{item.what.request.server_ip}}</code>
             </li>
             <li class="list-group-item">
-                {% if item.what.response.type == "Ok" %}
+                {% if item.what.response.type == "Ok" and messages[loop.index0] %}
+                    {% with message = messages[loop.index0] %}{% include "dnsmessage" %}{% endwith %}
+                    <details {% if item.what.response.resp.mode in ["ExplainEn", "ExplainRu"] %}open{% endif %}>
+                        <summary>As text (output mode is <code>{{ item.what.response.resp.mode }}</code>)</summary>
+                        <code><pre>{{ item.what.response.resp.text}}</pre></code>
+                    </details>
+                {% elif item.what.response.type == "Ok" %}
                     Answer (output mode is <code>{{ item.what.response.resp.mode }}</code>):
                     <code><pre>{{ item.what.response.resp.text}}</pre></code>
                 {% elif item.what.response.type == "QueryError" %}
@@ -123,33 +218,93 @@ const SESSION: &str = r#"
         <div class="card my-3 {% if item.what.status == "Correct" %}text-bg-success{% elif item.what.status == "Incorrect" %}text-bg-danger{% else %}border-warning{% endif %}">
             <h5 class="card-header">{{ item.when }}</h5>
             <div class="card-body">
-                <p>Submitted answer: <code>{{item.what.answer}}</code> (which is {% if item.what.status == "Correct" %}correct{% elif item.what.status == "Incorrect" %}incorrect{% else %}of unknown validity due to an error, please try again{% endif %}).</p>
+                <p>Submitted answer to question {{ item.what.question + 1 }}: <code>{{item.what.answer}}</code> (which is {% if item.what.status == "Correct" %}correct{% elif item.what.status == "Incorrect" %}incorrect{% else %}of unknown validity due to an error, please try again{% endif %}).</p>
             </div>
         </div>
 
+    {% elif item.what.type == "Locked" %}
+        <div class="card my-3 border-secondary">
+            <h5 class="card-header">{{ item.when }}</h5>
+            <div class="card-body">
+                <p>The session was locked. {% with reason = item.what.reason %}{% include "lockreason" %}{% endwith %}</p>
+            </div>
+        </div>
     {% endif %}
 {% endfor %}
 
 <hr>
 
-<div class="card my-3 border-info">
-    <h5 class="card-header">Question</h5>
+<div id="question" class="card my-3 border-info">
+    <div class="card-header">
+        <ul class="nav nav-tabs card-header-tabs">
+            {% for item in session.questions %}
+            <li class="nav-item">
+                <a class="nav-link {% if loop.index0 == current %}active{% endif %}" href="?q={{ loop.index }}#question">
+                    Question {{ loop.index }}
+                    {% if item.status == "Correct" %}<span class="badge text-bg-success">answered</span>
+                    {% elif item.status == "OutOfAttempts" %}<span class="badge text-bg-danger">no attempts left</span>{% endif %}
+                    {% if read_only %}<span class="badge text-bg-secondary">{{ scores[loop.index0].points }}/{{ scores[loop.index0].max_points }} points</span>{% endif %}
+                </a>
+            </li>
+            {% endfor %}
+        </ul>
+    </div>
     <p class="card-body">
-        {{session.question.text}}
+        {% if question %}{{ question.text }}{% else %}This session has no questions.{% endif %}
     </p>
+    {% if hint %}
+    <details class="card-body">
+        <summary>Hint</summary>
+        {{ hint }}
+    </details>
+    {% endif %}
+    {% if path %}
+    <div class="card-body row">
+        <div class="col-md-6">
+            <h5>How to find the answer</h5>
+            <ol>
+            {% for step in path.reference %}
+            <li class="my-3">
+                <code>dig @{{ step.server_ip }} IN {{ step.record_type }} {{ step.name }}</code>
+                {% if step.asked %}<span class="badge text-bg-success">you asked this</span>{% endif %}
+                <br>&rarr; {{ step.outcome }}
+                {% with message = step.message %}{% include "dnsmessage" %}{% endwith %}
+            </li>
+            {% endfor %}
+            </ol>
+        </div>
+        <div class="col-md-6">
+            <h5>Your queries for this question</h5>
+            {% if path.own %}
+            <ol>
+            {% for query in path.own %}
+            <li class="my-3">
+                <code>dig @{{ query.server_ip }} IN {{ query.record_type }} {{ query.name }}</code>
+                {% if query.on_path %}<span class="badge text-bg-success">on the path</span>{% endif %}
+                <br>&rarr; {{ query.outcome }} <small class="text-body-secondary">({{ query.when }})</small>
+            </li>
+            {% endfor %}
+            </ol>
+            {% else %}
+            <p>You made no queries while this question was shown.</p>
+            {% endif %}
+        </div>
+    </div>
+    {% endif %}
 </div>
 
 
+{% if not read_only %}
 <hr>
-<div class="{% if session.can_answer %}{% else %}noanswer{% endif %}">
+<div class="{% if can_act %}{% else %}noanswer{% endif %}">
     <div id="new-query" class="my-3">
         <h2>New query:</h2>
-        <form method=POST>
+        <form method=POST action="?q={{ current + 1 }}">
             <div class="input-group">
             <span class="input-group-text"><code>dig @</code></span>
-            <input class="form-control" type=text name="ip" placeholder="NS server IP" style="flex: 5;" {% if session.can_answer %}{% else %}disabled{% endif %}/>
+            <input class="form-control" type=text id="query-ip" name="ip" placeholder="NS server IP" style="flex: 5;" {% if can_act %}{% else %}disabled{% endif %}/>
             <span class="input-group-text"><code> IN </code></span>
-            <select name="class" class="form-control" style="flex: 1;" {% if session.can_answer %}{% else %}disabled{% endif %}>
+            <select id="query-type" name="class" class="form-control" style="flex: 1;" {% if can_act %}{% else %}disabled{% endif %}>
                 <option value="A" selected>A</option>
                 <option value="AAAA">AAAA</option>
                 <option value="ANAME">ANAME</option>
@@ -185,43 +340,57 @@ const SESSION: &str = r#"
                 <option value="TSIG">TSIG</option>
                 <option value="TXT">TXT</option>
             </select>
-            <input type=text class="form-control" name="name" placeholder="Domain name" style="flex: 5;" {% if session.can_answer %}{% else %}disabled{% endif %}/>
+            <input type=text class="form-control" id="query-name" name="name" placeholder="Domain name" style="flex: 5;" {% if can_act %}{% else %}disabled{% endif %}/>
             <input type=hidden name="action" value="Query" />
-            <input type=submit  class="btn btn-outline-success" value="Go!" {% if session.can_answer %}{% else %}disabled{% endif %} />
+            <input type=submit  class="btn btn-outline-success" value="Go!" {% if can_act %}{% else %}disabled{% endif %} />
         </form>
     </div>
     <div class="my-3">
-        <form method=POST>
+        <form method=POST action="?q={{ current + 1 }}">
         <div class="input-group">
         <span class="input-group-text">New output mode:</span>
-        <select name="mode" class="form-control" style="flex: 1;" {% if session.can_answer %}{% else %}disabled{% endif %}>
+        <select name="mode" class="form-control" style="flex: 1;" {% if can_act %}{% else %}disabled{% endif %}>
             <option value="Classic">Classic</option>
             <option value="Rust">Rust</option>
+            <option value="Json">JSON</option>
+            {% if session.mode == "Practice" %}
+            <option value="ExplainEn">Explain (English)</option>
+            <option value="ExplainRu">Объяснение (по-русски)</option>
+            {% endif %}
         </select>
         <input type=hidden name="action" value="SetOutputMode" />
-        <input type=submit  class="btn btn-outline-success" value="Go!" {% if session.can_answer %}{% else %}disabled{% endif %} />
+        <input type=submit  class="btn btn-outline-success" value="Go!" {% if can_act %}{% else %}disabled{% endif %} />
         </form>
     </div>
+    {% if question %}
+    {% set can_answer = can_act and question.status == "Open" %}
     <div class="my-3">
         <form method=POST>
         <div class="input-group">
-        <span class="input-group-text">Submit an answer:</span>
-        <span class="input-group-text text-bg-warning">({{ session.answers_remaining }} attempts left)</span>
-        <input type=text class="form-control" name="answer" style="flex: 5;" {% if session.can_answer %}{% else %}disabled{% endif %}/>
+        <span class="input-group-text">Answer to question {{ current + 1 }}:</span>
+        <span class="input-group-text text-bg-warning">({% if session.mode == "Practice" %}unlimited attempts{% else %}{{ question.answers_remaining }} attempts left{% endif %})</span>
+        <input type=text class="form-control" name="answer" style="flex: 5;" {% if can_answer %}{% else %}disabled{% endif %}/>
 
+        <input type=hidden name="question" value="{{ current }}"/>
         <input type=hidden name="action" value="SubmitAnswer"/>
-        <input type=submit  class="btn btn-danger" value="{% if session.can_answer %}Submit{% else %}Cannot submit{% endif %}!" {% if session.can_answer %}{% else %}disabled{% endif %}/>
+        <input type=submit  class="btn btn-danger" value="{% if can_answer %}Submit{% else %}Cannot submit{% endif %}!" {% if can_answer %}{% else %}disabled{% endif %}/>
         </form>
+        {% if can_act and not can_answer %}
+        <p class="form-text">{% with status = question.status %}{% include "questionstatus" %}{% endwith %}</p>
+        {% endif %}
     </div>
+    {% endif %}
     <div class="noanswer-stroke">
-    {% if session.can_answer %}{% else %}
+    {% if can_act %}{% else %}
         <h1></h1>
         <h1 class="text-center" style="margin: auto;">
         Cannot submit query at this time.
         </h1>
+        {% if lock_reason %}<p class="text-center">{% with reason = lock_reason %}{% include "lockreason" %}{% endwith %}</p>{% endif %}
     {% endif %}
     </div>
 </div>
+{% endif %}
 <style>
     .noanswer {
         position: relative;
@@ -249,6 +418,36 @@ const SESSION: &str = r#"
         flex-direction: column;
     }
 </style>
+<script>
+    // Records that point to a server fill in the query form with the query to send to it.
+    for (const element of document.querySelectorAll(".follow")) {
+        element.addEventListener("click", () => {
+            document.getElementById("query-ip").value = element.dataset.ip;
+            document.getElementById("query-type").value = element.dataset.type;
+            document.getElementById("query-name").value = element.dataset.name;
+            document.getElementById("new-query").scrollIntoView();
+        });
+    }
+
+    // Count down to the moment the session opens or closes, then reload to show what changed.
+    // The time left comes from the server, so that a wrong clock on the student's computer does not matter.
+    for (const element of document.querySelectorAll(".countdown")) {
+        const end = Date.now() + (Number(element.dataset.seconds) + 1) * 1000;
+        const update = () => {
+            const left = Math.max(0, Math.floor((end - Date.now()) / 1000));
+            const hours = Math.floor(left / 3600);
+            const minutes = String(Math.floor(left / 60) % 60).padStart(2, "0");
+            const seconds = String(left % 60).padStart(2, "0");
+            element.textContent = `${hours}:${minutes}:${seconds}`;
+            if (left > 0) {
+                setTimeout(update, 1000);
+            } else {
+                location.reload();
+            }
+        };
+        update();
+    }
+</script>
 {% endblock %}
 "#;
 
@@ -264,10 +463,43 @@ pub enum SessionRequest {
         mode: OutputMode,
     },
     SubmitAnswer {
+        /// Index of the question in the session.
+        #[serde(deserialize_with = "parse_field")]
+        question: usize,
         answer: String,
     },
 }
 
+/// Form fields inside an enum with a tag are always passed on as strings,
+/// so fields of other types have to be parsed from them.
+fn parse_field<'de, D, T>(deserializer: D) -> Result<T, D::Error>
+where
+    D: serde::Deserializer<'de>,
+    T: std::str::FromStr,
+    T::Err: std::fmt::Display,
+{
+    let text = String::deserialize(deserializer)?;
+    text.parse().map_err(serde::de::Error::custom)
+}
+
+#[derive(Deserialize, Debug)]
+pub struct SessionView {
+    /// Number of the question to show, starting from 1.
+    pub q: Option<usize>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct TraceRequest {
+    #[serde(default)]
+    pub format: TraceFormat,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ExportRequest {
+    #[serde(default)]
+    pub format: ExportFormat,
+}
+
 const ERROR_SAVING: &str = r#"
 {% extends "theme" %}
 {% block head %}
@@ -282,15 +514,131 @@ const ERROR_SAVING: &str = r#"
 {% endblock %}
 "#;
 
-const NO_ATTEMPTS: &str = r#"
+const LOCKED: &str = r#"
+{% extends "theme" %}
+{% block head %}
+<title>Session locked</title>
+{% endblock %}
+{% block body %}
+<div class="px-4 py-5 text-center">
+    <h1 class="display-2 fw-bold">Session locked</h1>
+    <p>{% include "lockreason" %} Your latest action has not been saved.</p>
+    <a href="/{{ key }}?aftererror=1" class="btn btn-primary">Return to your session</a>
+</div>
+{% endblock %}
+"#;
+
+const QUESTION_CLOSED: &str = r#"
 {% extends "theme" %}
 {% block head %}
-<title>No attempts left</title>
+<title>Question closed</title>
 {% endblock %}
 {% block body %}
 <div class="px-4 py-5 text-center">
-    <h1 class="display-2 fw-bold">No attempts left</h1>
-    <p>You have exceeded your attempt limit. Your latest action has not been saved.</p>
+    <h1 class="display-2 fw-bold">Question closed</h1>
+    <p>{% if status %}{% include "questionstatus" %}{% else %}There is no question {{ number }} in this session.{% endif %}
+    Your latest action has not been saved.</p>
+    <a href="/{{ key }}?q={{ number }}&aftererror=1" class="btn btn-primary">Return to your session</a>
+</div>
+{% endblock %}
+"#;
+
+const MODE_UNAVAILABLE: &str = r#"
+{% extends "theme" %}
+{% block head %}
+<title>Output mode not available</title>
+{% endblock %}
+{% block body %}
+<div class="px-4 py-5 text-center">
+    <h1 class="display-2 fw-bold">Output mode not available</h1>
+    <p>Explanations are only available in practice sessions.
+    Your latest action has not been saved.</p>
+    <a href="/{{ key }}?aftererror=1" class="btn btn-primary">Return to your session</a>
+</div>
+{% endblock %}
+"#;
+
+const QUESTION_STATUS: &str = r#"
+{%- if status == "Correct" -%}
+You have answered this question correctly.
+{%- elif status == "OutOfAttempts" -%}
+You have no attempts left for this question.
+{%- else -%}
+This question is open.
+{%- endif -%}
+"#;
+
+/// A response from a server, shown as tables of its sections.
+/// Needs `message` and `can_act`; records that point to a server can be clicked to query it when `can_act` is set.
+const DNS_MESSAGE: &str = r#"
+<details open>
+    <summary>
+        Response
+        {% for flag in message.flags %}
+        <span class="badge {% if flag[1] %}text-bg-primary{% else %}text-bg-secondary opacity-50{% endif %}" title="{% if flag[1] %}set{% else %}not set{% endif %}">{{ flag[0] }}</span>
+        {% endfor %}
+        <span class="badge {% if message.no_error %}text-bg-success{% else %}text-bg-danger{% endif %}">{{ message.rcode }}</span>
+    </summary>
+    {% for section in [("Question", message.question), ("Answer", message.answers), ("Authority", message.authority), ("Additional", message.additional)] %}
+    <details {% if section[1] %}open{% endif %}>
+        <summary>{{ section[0] }} ({{ section[1] | length }})</summary>
+        {% if section[1] %}
+        <table class="table table-sm">
+            <thead><tr><th>Name</th><th>TTL</th><th>Class</th><th>Type</th><th>Data</th></tr></thead>
+            <tbody>
+            {% for record in section[1] %}
+            {% if can_act and record.follow %}
+            <tr class="follow" role="button" data-ip="{{ record.follow.server_ip or "" }}" data-type="{{ record.follow.record_type }}" data-name="{{ record.follow.name }}"
+                title="{% if record.follow.server_ip %}Ask {{ record.follow.server_ip }} about {{ record.follow.name }}{% else %}Find the address of {{ record.follow.name }}{% endif %}">
+            {% else %}
+            <tr>
+            {% endif %}
+                <td><code>{{ record.name }}</code></td>
+                <td>{{ record.ttl or "" }}</td>
+                <td>{{ record.class }}</td>
+                <td>{{ record.record_type }}</td>
+                <td><code>{{ record.data }}</code>{% if can_act and record.follow %} &#x21b5;{% endif %}</td>
+            </tr>
+            {% endfor %}
+            </tbody>
+        </table>
+        {% endif %}
+    </details>
+    {% endfor %}
+    {% if message.explanations %}
+    <details open>
+        <summary>Explanation</summary>
+        <pre>{{ message.explanations[0] }}</pre>
+    </details>
+    <details>
+        <summary>Объяснение</summary>
+        <pre>{{ message.explanations[1] }}</pre>
+    </details>
+    {% endif %}
+</details>
+"#;
+
+const LOCK_REASON: &str = r#"
+{%- if reason == "Finished" -%}
+You have finished all of the questions.
+{%- elif reason == "DeadlinePassed" -%}
+The deadline for this session has passed.
+{%- elif reason == "TimeUp" -%}
+Your time for this session is over.
+{%- else -%}
+This session does not accept any more actions.
+{%- endif -%}
+"#;
+
+const NOT_OPEN: &str = r#"
+{% extends "theme" %}
+{% block head %}
+<title>Session not open yet</title>
+{% endblock %}
+{% block body %}
+<div class="px-4 py-5 text-center">
+    <h1 class="display-2 fw-bold">Not open yet</h1>
+    <p>This session opens at {{ opens_at }}. Your latest action has not been saved.</p>
     <a href="/{{ key }}?aftererror=1" class="btn btn-primary">Return to your session</a>
 </div>
 {% endblock %}
@@ -306,6 +654,10 @@ const HOME: &str = r#"
 <div class="px-4 py-5 text-center">
     <h1 class="display-2 fw-bold">DNS</h1>
     <p>Please enter your session key to begin your task.</p>
+    {% if invalid_key %}
+    <div class="alert alert-danger">This is not a valid session key.
+    Session keys consist of latin letters, digits, <code>-</code> and <code>_</code>.</div>
+    {% endif %}
     <form method=POST>
         <div class="input-group">
         <span class="input-group-text">Session key:</span>
@@ -318,6 +670,196 @@ const HOME: &str = r#"
 {% endblock %}
 "#;
 
+const SESSION_LOGIN: &str = r#"
+{% extends "theme" %}
+{% block head %}
+<title>Log in</title>
+{% endblock %}
+{% block body %}
+<div class="px-4 py-5 text-center">
+    <h1 class="display-2 fw-bold">Log in</h1>
+    <p>The session <code>{{ key }}</code> is protected with a password. Please enter the password you were given.</p>
+    {% if wrong_password %}
+    <div class="alert alert-danger">This password is not right.</div>
+    {% endif %}
+    <form method=POST action="/{{ key }}/login">
+        <div class="input-group">
+        <span class="input-group-text">Password:</span>
+        <input type=password class="form-control" name="password" style="flex: 5;" autofocus/>
+        <input type=submit class="btn btn-outline-success" value="Go!"/>
+        </div>
+    </form>
+</div>
+{% endblock %}
+"#;
+
+const ADMIN_LOGIN: &str = r#"
+{% extends "theme" %}
+{% block head %}
+<title>Log in</title>
+{% endblock %}
+{% block body %}
+<div class="px-4 py-5 text-center">
+    <h1 class="display-2 fw-bold">Teachers only</h1>
+    <p>Please log in with the admin password to see the sessions.</p>
+    {% if wrong_password %}
+    <div class="alert alert-danger">This password is not right.</div>
+    {% endif %}
+    <form method=POST action="/admin/login">
+        <div class="input-group">
+        <span class="input-group-text">Admin password:</span>
+        <input type=password class="form-control" name="password" style="flex: 5;" autofocus/>
+        <input type=submit class="btn btn-outline-success" value="Log in"/>
+        </div>
+    </form>
+</div>
+{% endblock %}
+"#;
+
+const ADMIN_DISABLED: &str = r#"
+{% extends "notfound" %}
+{% block not_found_what %}
+<p>The teacher's pages are disabled, because no admin password hash is set in the server configuration.</p>
+{% endblock %}
+"#;
+
+/// A report on everything that happened in a session, to be saved with the grades.
+/// It does not use the theme, so that it can be opened without network access.
+const TRACE: &str = r#"
+<!doctype html>
+<html>
+<head>
+<meta charset="utf-8">
+<title>Session {{ key }}</title>
+<style>
+    body { font-family: sans-serif; margin: 2em; }
+    table { border-collapse: collapse; width: 100%; margin-bottom: 2em; }
+    th, td { border: 1px solid #999; padding: 0.3em 0.5em; text-align: left; vertical-align: top; }
+    th { background: #eee; }
+    pre { margin: 0; white-space: pre-wrap; }
+    .correct { background: #dfd; }
+    .incorrect { background: #fdd; }
+    .error { background: #ffd; }
+</style>
+</head>
+<body>
+<h1>Session <code>{{ key }}</code></h1>
+<p>Report made at {{ generated_at }}. Total score: {{ score }} of {{ max_score }}.
+{% if lock_reason %}{% with reason = lock_reason %}{% include "lockreason" %}{% endwith %}{% else %}The session is not locked.{% endif %}</p>
+
+<h2>Questions</h2>
+<table>
+    <tr><th>#</th><th>Question</th><th>Status</th><th>Queries</th><th>Wrong answers</th><th>Points</th></tr>
+    {% for question in session.questions %}
+    {% set score = scores[loop.index0] %}
+    <tr>
+        <td>{{ loop.index }}</td>
+        <td>{{ question.text }}</td>
+        <td>{{ question.status }}</td>
+        <td>{{ score.queries }}</td>
+        <td>{{ score.wrong_answers }}</td>
+        <td>{{ score.points }} of {{ score.max_points }}</td>
+    </tr>
+    {% endfor %}
+</table>
+
+<h2>History</h2>
+<table>
+    <tr><th>#</th><th>Time</th><th>Question</th><th>Action</th><th>Details</th></tr>
+    {% for item in session.user_requests %}
+    {% set what = item.what %}
+    <tr class="{% if what.type == "SubmitAnswer" %}{{ what.status | lower }}{% endif %}">
+        <td>{{ loop.index }}</td>
+        <td>{{ item.when }}</td>
+        <td>{% if what.question is defined %}{{ what.question + 1 }}{% endif %}</td>
+        {% if what.type == "Request" %}
+        <td>Query <code>IN {{ what.request.record_type }} {{ what.request.name }}</code> &rarr; <code>{{ what.request.server_ip }}</code>
+            {% if analysis[loop.index0] %}{% with query = analysis[loop.index0] %}<br><em>{{ query.label }}{% if query.shortcut %}, shortcut: this server had not been found yet{% endif %}</em>{% endwith %}{% endif %}</td>
+        <td>
+            {% if what.response.type == "Ok" %}<pre>{{ what.response.resp.text }}</pre>
+            {% elif what.response.type == "QueryError" %}Error while querying: <code>{{ what.response.err }}</code>
+            {% elif what.response.type == "ForbiddenRecursion" %}Querying this server is not allowed: <code>{{ what.response.addr }}</code>
+            {% elif what.response.type == "InvalidRequestIpAddr" %}Not a valid IP address: <code>{{ what.response.addr }}</code>
+            {% endif %}
+        </td>
+        {% elif what.type == "SwitchOutputMode" %}
+        <td>Switched output mode</td>
+        <td><code>{{ what.new_mode }}</code></td>
+        {% elif what.type == "SubmitAnswer" %}
+        <td>Answer</td>
+        <td><code>{{ what.answer }}</code> ({{ what.status }})</td>
+        {% elif what.type == "Locked" %}
+        <td>Locked</td>
+        <td>{% with reason = what.reason %}{% include "lockreason" %}{% endwith %}</td>
+        {% endif %}
+    </tr>
+    {% else %}
+    <tr><td colspan="5">Nothing was done in this session.</td></tr>
+    {% endfor %}
+</table>
+</body>
+</html>
+"#;
+
+const ADMIN_SESSIONS: &str = r#"
+{% extends "theme" %}
+{% block head %}
+<title>Sessions</title>
+{% endblock %}
+{% block body %}
+<h1>Sessions</h1>
+<div class="my-3">
+    <a class="btn btn-outline-primary" href="/admin/grades?format=csv">Grades (CSV)</a>
+    <a class="btn btn-outline-primary" href="/admin/grades?format=json">Grades (JSON)</a>
+    <form method=POST action="/admin/logout" class="d-inline"><input type=submit class="btn btn-outline-secondary" value="Log out"/></form>
+</div>
+<table class="table table-hover">
+    <thead>
+        <tr>
+            <th>Key</th>
+            <th>Question</th>
+            <th>Solved</th>
+            <th>Attempts left</th>
+            <th>Status</th>
+            <th>Queries</th>
+            <th>Shortcuts</th>
+            <th>Last activity</th>
+            <th>Score</th>
+        </tr>
+    </thead>
+    <tbody>
+        {% for row in rows %}
+        {% if row.error %}
+        <tr class="table-danger">
+            <td><a href="/admin/{{ row.key }}"><code>{{ row.key }}</code></a></td>
+            <td colspan="8">Could not read the session: {{ row.error }}</td>
+        </tr>
+        {% else %}
+        <tr>
+            <td><a href="/admin/{{ row.key }}"><code>{{ row.key }}</code></a>{% if row.has_password %} <span class="badge text-bg-secondary">password</span>{% endif %}</td>
+            <td>{{ row.first_question }}{% if row.questions > 1 %} <span class="badge text-bg-secondary">+{{ row.questions - 1 }} more</span>{% endif %}</td>
+            <td>{{ row.correct }}/{{ row.questions }}</td>
+            <td>{{ row.attempts_left }}</td>
+            <td>{{ row.status }}{% if row.lock_reason %}: {% with reason = row.lock_reason %}{% include "lockreason" %}{% endwith %}{% endif %}</td>
+            <td>{{ row.queries }}</td>
+            <td>{% if row.shortcuts %}<span class="badge text-bg-danger">{{ row.shortcuts }}</span>{% else %}0{% endif %}</td>
+            <td>{% if row.last_activity %}{{ row.last_activity }}{% else %}never{% endif %}</td>
+            <td>{{ row.score }}/{{ row.max_score }}</td>
+        </tr>
+        {% endif %}
+        {% else %}
+        <tr><td colspan="9">There are no sessions yet.</td></tr>
+        {% endfor %}
+    </tbody>
+</table>
+{% endblock %}
+"#;
+
+#[derive(Deserialize)]
+pub struct LoginRequest {
+    pub password: String,
+}
+
 #[derive(Deserialize, Debug)]
 pub struct SessionKeyRequest {
     pub key: String,
@@ -1,72 +1,142 @@
-use std::net::{IpAddr, SocketAddr};
+use std::net::IpAddr;
 
-use super::templates::{env, SessionRequest};
+use super::auth::{check_password, clear_cookie, login_duration, SESSION_COOKIE};
+use super::templates::{env, LoginRequest, SessionRequest, SessionView};
+use super::AppState;
 use axum::{
-    extract::Path,
-    http::StatusCode,
+    extract::{Path, Query, State},
+    http::{header, HeaderMap, HeaderName, HeaderValue, StatusCode},
     response::{Html, Redirect},
     Form,
 };
-use chrono::Utc;
-use minijinja::context;
+use chrono::{DateTime, Utc};
+use minijinja::{context, Environment};
 
 use crate::{
-    querying::{is_recursive_server, perform_query, validate_answer},
-    session::{get_session, set_session, AnswerStatus, Event, Request, RequestLogEntry, Response},
+    output::{message_views, revealed_path},
+    questions::hint,
+    querying::{perform_query, validate_answer},
+    session::{
+        AnswerStatus, Event, LockReason, QuestionStatus, Request, RequestLogEntry, Response,
+        Session, SessionError, SessionKey, SessionMode, Window,
+    },
 };
 
-pub async fn session_get(Path(key): Path<String>) -> (StatusCode, Html<String>) {
+pub async fn session_get(
+    State(state): State<AppState>,
+    Path(key): Path<String>,
+    Query(view): Query<SessionView>,
+    headers: HeaderMap,
+) -> (StatusCode, Html<String>) {
     let env = env();
-    let session = get_session(&key).await;
-    if session.is_none() {
-        return (
-            StatusCode::NOT_FOUND,
-            Html(
-                env.get_template("sessionnotfound")
-                    .unwrap()
-                    .render(context!(key => key))
-                    .unwrap(),
-            ),
-        );
+    let Ok(key) = SessionKey::new(key) else {
+        return invalid_key_page(&env);
+    };
+    let now = Utc::now();
+    let session = match state.sessions.get(&key).await {
+        Ok(session) => session,
+        Err(error) => return session_error_page(&env, &key, &error),
+    };
+    if !logged_in(&state, &headers, &key, &session, now) {
+        return login_page(&env, &key, false);
     }
-    let session = session.unwrap();
+    let mut session = match follow_schedule(&state, &key, session, now).await {
+        Ok(session) => session,
+        Err(error) => return session_error_page(&env, &key, &error),
+    };
+    session.render_responses();
+    // Countdowns, in seconds.
+    let (opens_in, time_left) = match session.window(now) {
+        Window::NotOpenYet { opens_at } => (Some((opens_at - now).num_seconds()), None),
+        Window::Open { deadline } => {
+            (None, deadline.map(|deadline| (deadline - now).num_seconds()))
+        }
+        Window::Over(_) => (None, None),
+    };
+    let current = session.current_question(view.q);
+    let world = state.world_for(&key).await;
     let html = env
         .get_template("session")
         .unwrap()
-        .render(context!(session => session))
+        .render(context!(
+            key => key,
+            current => current,
+            question => session.questions.get(current),
+            hint => session.questions.get(current)
+                .filter(|_| session.mode == SessionMode::Practice)
+                .map(|question| hint(&question.answer)),
+            path => revealed_path(&world, &session, current),
+            can_act => session.can_answer && opens_in.is_none(),
+            opens_in => opens_in.filter(|_| session.can_answer),
+            time_left => time_left.filter(|_| session.can_answer),
+            lock_reason => session.lock_reason(),
+            has_password => session.password_hash.is_some(),
+            messages => message_views(&session),
+            session => session,
+            root_servers => world.root_server_addresses(),
+        ))
         .unwrap();
     (StatusCode::OK, Html(html))
 }
 pub async fn session_post(
+    State(state): State<AppState>,
     Path(key): Path<String>,
+    Query(view): Query<SessionView>,
+    headers: HeaderMap,
     Form(request): Form<SessionRequest>,
 ) -> Result<Redirect, (StatusCode, Html<String>)> {
     let env = env();
-    let session = get_session(&key).await;
-    if session.is_none() {
-        return Err((
-            StatusCode::NOT_FOUND,
-            Html(
-                env.get_template("sessionnotfound")
-                    .unwrap()
-                    .render(context!(key => key))
-                    .unwrap(),
-            ),
-        ));
+    let Ok(key) = SessionKey::new(key) else {
+        return Err(invalid_key_page(&env));
+    };
+    // Held until the changes are saved, so that concurrent requests do not interleave.
+    let _lock = state.session_locks.lock(&key).await;
+    let mut session = match state.sessions.get(&key).await {
+        Ok(session) => session,
+        Err(error) => return Err(session_error_page(&env, &key, &error)),
+    };
+    let now = Utc::now();
+    if !logged_in(&state, &headers, &key, &session, now) {
+        return Err(login_page(&env, &key, false));
+    }
+    let world = state.world_for(&key).await;
+    if session.can_answer {
+        match session.window(now) {
+            Window::NotOpenYet { opens_at } => {
+                return Err((
+                    StatusCode::FORBIDDEN,
+                    Html(
+                        env.get_template("notopen")
+                            .unwrap()
+                            .render(context!(key => key, opens_at => opens_at))
+                            .unwrap(),
+                    ),
+                ));
+            }
+            Window::Over(reason) => {
+                let entry = RequestLogEntry {
+                    when: now,
+                    what: session.lock(reason),
+                };
+                if let Err(e) = state.sessions.append(&key, session, vec![entry]).await {
+                    tracing::error!("Could not save session {key}: {e}");
+                    return Err(error_saving_page(&env, &key));
+                }
+                return Err(locked_page(&env, &key, Some(reason)));
+            }
+            Window::Open { .. } => {
+                session.start(now);
+            }
+        }
     }
-    let mut session = session.unwrap();
     if !session.can_answer {
-        return Err((
-            StatusCode::BAD_REQUEST,
-            Html(
-                env.get_template("noattempts")
-                    .unwrap()
-                    .render(context!(key => key))
-                    .unwrap(),
-            ),
-        ));
+        return Err(locked_page(&env, &key, session.lock_reason()));
     }
 
+    let mut lock_reason = None;
+    // The question to show after the action.
+    let mut shown = view.q;
+    let current = session.current_question(view.q);
     let event = match request {
         SessionRequest::Query { ip, class, name } => {
             let ip = ip.trim();
@@ -79,46 +149,27 @@ pub async fn session_post(
             // Try parsing the query IP address.
             let maybe_parsed_ip = ip.parse::<IpAddr>();
             if let Ok(parsed_ip) = maybe_parsed_ip {
-                // First perform the query the user asked for.
-                let ip = SocketAddr::new(parsed_ip, 53);
-                let true_response = perform_query(ip, &name, class).await;
+                let true_response = perform_query(&world, parsed_ip, name, class);
                 match true_response {
-                    Ok(resp) => {
-                        // We have a response, so the server exists.
-                        // But is it a recursive server?
-                        if is_recursive_server(ip).await {
-                            Event::Request {
-                                request,
-                                response: crate::session::ResponseResult::ForbiddenRecursion {
-                                    addr: ip.ip(),
-                                },
-                            }
-                        } else {
-                            let text = match session.current_output_mode {
-                                crate::session::OutputMode::Classic => resp.to_string(),
-                                crate::session::OutputMode::Rust => format!("{resp:#?}"),
-                            };
-                            Event::Request {
-                                request,
-                                response: crate::session::ResponseResult::Ok {
-                                    resp: Response {
-                                        text,
-                                        mode: session.current_output_mode,
-                                    },
-                                },
-                            }
-                        }
-                    }
+                    Ok(resp) => Event::Request {
+                        question: current,
+                        request,
+                        response: crate::session::ResponseResult::Ok {
+                            resp: Response::new(&resp, session.current_output_mode),
+                        },
+                    },
                     Err(error) => Event::Request {
+                        question: current,
                         request,
                         response: crate::session::ResponseResult::QueryError {
-                            err: format!("{error:?}"),
+                            err: error.to_string(),
                         },
                     },
                 }
             } else {
                 // Add error record
                 Event::Request {
+                    question: current,
                     request,
                     response: crate::session::ResponseResult::InvalidRequestIpAddr {
                         addr: ip.to_owned(),
@@ -127,43 +178,276 @@ pub async fn session_post(
             }
         }
         SessionRequest::SetOutputMode { mode } => {
+            if !mode.available_in(session.mode) {
+                return Err(mode_unavailable_page(&env, &key));
+            }
             session.current_output_mode = mode;
             Event::SwitchOutputMode { new_mode: mode }
         }
-        SessionRequest::SubmitAnswer { answer } => {
+        SessionRequest::SubmitAnswer { question: index, answer } => {
+            let Some(question) = session.questions.get(index) else {
+                return Err(question_closed_page(&env, &key, index, None));
+            };
+            if question.status != QuestionStatus::Open {
+                return Err(question_closed_page(&env, &key, index, Some(question.status)));
+            }
+            let practice = session.mode == SessionMode::Practice;
+            // A session edited by hand may leave a question open with no attempts left.
+            if question.answers_remaining == 0 && !practice {
+                let status = Some(QuestionStatus::OutOfAttempts);
+                return Err(question_closed_page(&env, &key, index, status));
+            }
             let answer = answer.trim().to_owned();
-            let status = match validate_answer(&session.question.answer, &answer).await {
+            let status = match validate_answer(&world, &question.answer, &answer) {
                 Some(true) => AnswerStatus::Correct,
                 Some(false) => AnswerStatus::Incorrect,
                 None => AnswerStatus::Error,
             };
-            if !matches!(status, AnswerStatus::Error) {
-                session.answers_remaining -= 1;
+            let question = &mut session.questions[index];
+            // Practice sessions do not count attempts.
+            if !matches!(status, AnswerStatus::Error) && !practice {
+                question.answers_remaining = question.answers_remaining.saturating_sub(1);
             }
 
-            if matches!(status, AnswerStatus::Correct) || session.answers_remaining==0 {
-                session.can_answer = false;
+            if matches!(status, AnswerStatus::Correct) {
+                question.status = QuestionStatus::Correct;
+            } else if question.answers_remaining == 0 && !practice {
+                question.status = QuestionStatus::OutOfAttempts;
+            }
+            if session.is_finished() {
+                lock_reason = Some(LockReason::Finished);
             }
 
-
-            Event::SubmitAnswer { answer, status }
+            shown = Some(index + 1);
+            Event::SubmitAnswer { question: index, answer, status }
         }
     };
-    session.user_requests.push(RequestLogEntry {
-        when: Utc::now(),
+    let mut entries = vec![RequestLogEntry {
+        when: now,
         what: event,
-    });
+    }];
+    if let Some(reason) = lock_reason {
+        entries.push(RequestLogEntry {
+            when: now,
+            what: session.lock(reason),
+        });
+    }
 
-    if set_session(&key, session).await.is_none() {
-        return Err((
+    session.store_scores(&entries);
+    if let Err(e) = state.sessions.append(&key, session, entries).await {
+        tracing::error!("Could not save session {key}: {e}");
+        return Err(error_saving_page(&env, &key));
+    }
+    Ok(Redirect::to(&match shown {
+        Some(q) => format!("/{key}?q={q}#new-query"),
+        None => format!("/{key}#new-query"),
+    }))
+}
+
+pub async fn login(
+    State(state): State<AppState>,
+    Path(key): Path<String>,
+    Form(request): Form<LoginRequest>,
+) -> Result<([(HeaderName, HeaderValue); 1], Redirect), (StatusCode, Html<String>)> {
+    let env = env();
+    let Ok(key) = SessionKey::new(key) else {
+        return Err(invalid_key_page(&env));
+    };
+    let _lock = state.login_locks.lock(&key).await;
+    let session = match state.sessions.get(&key).await {
+        Ok(session) => session,
+        Err(error) => return Err(session_error_page(&env, &key, &error)),
+    };
+    let hash = session.password_hash.unwrap_or_default();
+    if !hash.is_empty() && !check_password(hash.clone(), request.password).await {
+        tracing::warn!("Wrong password for session {key}");
+        return Err(login_page(&env, &key, true));
+    }
+    let cookie = state.cookies.set_login(
+        SESSION_COOKIE,
+        key.as_str(),
+        &hash,
+        Utc::now() + login_duration(),
+    );
+    Ok(([(header::SET_COOKIE, cookie)], Redirect::to(&format!("/{key}"))))
+}
+
+pub async fn logout() -> ([(HeaderName, HeaderValue); 1], Redirect) {
+    (
+        [(header::SET_COOKIE, clear_cookie(SESSION_COOKIE))],
+        Redirect::to("/"),
+    )
+}
+
+/// Whether this browser may see and act in the session.
+/// Sessions without a password are open to anyone who knows the key;
+/// others only to the browser that logged in to them with their current password.
+fn logged_in(
+    state: &AppState,
+    headers: &HeaderMap,
+    key: &SessionKey,
+    session: &Session,
+    now: DateTime<Utc>,
+) -> bool {
+    match &session.password_hash {
+        None => true,
+        Some(hash) => state
+            .cookies
+            .logged_in(headers, SESSION_COOKIE, key.as_str(), hash, now),
+    }
+}
+
+/// The page that asks for the password of a session.
+fn login_page(
+    env: &Environment,
+    key: &SessionKey,
+    wrong_password: bool,
+) -> (StatusCode, Html<String>) {
+    let html = env
+        .get_template("sessionlogin")
+        .unwrap()
+        .render(context!(key => key, wrong_password => wrong_password))
+        .unwrap();
+    (StatusCode::UNAUTHORIZED, Html(html))
+}
+
+/// The page to show when a session could not be read.
+/// Anything other than a wrong key is a problem on the server side, so it is logged.
+pub(super) fn session_error_page(
+    env: &Environment,
+    key: &SessionKey,
+    error: &SessionError,
+) -> (StatusCode, Html<String>) {
+    if !matches!(error, SessionError::NotFound) {
+        tracing::error!("Could not read session {key}: {error}");
+    }
+    let (status, template, ctx) = match error {
+        SessionError::NotFound => (
+            StatusCode::NOT_FOUND,
+            "sessionnotfound",
+            context!(key => key),
+        ),
+        SessionError::Corrupt {
+            line,
+            column,
+            message,
+        } => (
             StatusCode::INTERNAL_SERVER_ERROR,
-            Html(
-                env.get_template("errorsaving")
-                    .unwrap()
-                    .render(context!(key => key))
-                    .unwrap(),
-            ),
-        ));
+            "sessioncorrupt",
+            context!(key => key, line => line, column => column, message => message),
+        ),
+        SessionError::VersionMismatch { found, supported } => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "sessionversion",
+            context!(key => key, found => found, supported => supported),
+        ),
+        SessionError::Io(_) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "sessionunavailable",
+            context!(key => key),
+        ),
+    };
+    let html = env.get_template(template).unwrap().render(ctx).unwrap();
+    (status, Html(html))
+}
+
+/// The page to show for a key that cannot belong to any session.
+/// The key itself is not shown, as it may contain anything at all.
+pub(super) fn invalid_key_page(env: &Environment) -> (StatusCode, Html<String>) {
+    let html = env
+        .get_template("invalidkey")
+        .unwrap()
+        .render(context!())
+        .unwrap();
+    (StatusCode::NOT_FOUND, Html(html))
+}
+
+/// Start the student's time when they first open the session, and lock the session
+/// once its time is over, so that it does not wait for the student to do anything.
+async fn follow_schedule(
+    state: &AppState,
+    key: &SessionKey,
+    session: Session,
+    now: DateTime<Utc>,
+) -> Result<Session, SessionError> {
+    if !schedule_changes(&session, now) {
+        return Ok(session);
     }
-    Ok(Redirect::to(&format!("/{key}#new-query")))
+    let _lock = state.session_locks.lock(key).await;
+    // The session may have changed while waiting for the lock.
+    let mut session = state.sessions.get(key).await?;
+    if !schedule_changes(&session, now) {
+        return Ok(session);
+    }
+    if let Window::Over(reason) = session.window(now) {
+        let entry = RequestLogEntry {
+            when: now,
+            what: session.lock(reason),
+        };
+        state.sessions.append(key, session, vec![entry]).await?;
+    } else {
+        session.start(now);
+        state.sessions.update_session(key, session).await?;
+    }
+    state.sessions.get(key).await
+}
+
+/// Whether the schedule of the session requires it to be started or locked.
+fn schedule_changes(session: &Session, now: DateTime<Utc>) -> bool {
+    session.can_answer
+        && match session.window(now) {
+            Window::NotOpenYet { .. } => false,
+            Window::Open { .. } => session.duration.is_some() && session.started_at.is_none(),
+            Window::Over(_) => true,
+        }
+}
+
+/// The page to show when an action is not accepted because the session is locked.
+fn locked_page(
+    env: &Environment,
+    key: &SessionKey,
+    reason: Option<LockReason>,
+) -> (StatusCode, Html<String>) {
+    let html = env
+        .get_template("locked")
+        .unwrap()
+        .render(context!(key => key, reason => reason))
+        .unwrap();
+    (StatusCode::BAD_REQUEST, Html(html))
+}
+
+/// The page to show when an answer is submitted to a question that does not accept answers,
+/// either because it is finished or because there is no such question.
+fn question_closed_page(
+    env: &Environment,
+    key: &SessionKey,
+    index: usize,
+    status: Option<QuestionStatus>,
+) -> (StatusCode, Html<String>) {
+    let html = env
+        .get_template("questionclosed")
+        .unwrap()
+        .render(context!(key => key, number => index + 1, status => status))
+        .unwrap();
+    (StatusCode::BAD_REQUEST, Html(html))
+}
+
+/// The page to show when the student asks for an output mode that their session does not offer.
+fn mode_unavailable_page(env: &Environment, key: &SessionKey) -> (StatusCode, Html<String>) {
+    let html = env
+        .get_template("modeunavailable")
+        .unwrap()
+        .render(context!(key => key))
+        .unwrap();
+    (StatusCode::BAD_REQUEST, Html(html))
+}
+
+/// The page to show when the changes to a session could not be saved.
+fn error_saving_page(env: &Environment, key: &SessionKey) -> (StatusCode, Html<String>) {
+    let html = env
+        .get_template("errorsaving")
+        .unwrap()
+        .render(context!(key => key))
+        .unwrap();
+    (StatusCode::INTERNAL_SERVER_ERROR, Html(html))
 }
@@ -0,0 +1,667 @@
+//! A simulated DNS hierarchy.
+//!
+//! Instead of sending packets to the real Internet, queries are answered in-process
+//! by a set of virtual authoritative servers (root, TLD and second-level) living at made-up addresses.
+
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    net::{IpAddr, Ipv4Addr},
+    sync::{Arc, Mutex},
+};
+
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use sha2::{Digest, Sha256};
+use trust_dns_client::{
+    op::{Message, MessageType, OpCode, Query, ResponseCode},
+    rr::{
+        rdata::{MX, SOA, TXT},
+        Name, RData, Record, RecordType,
+    },
+};
+
+use tokio::sync::OnceCell;
+
+use crate::querying::QueryError;
+
+pub use self::data::{load_secret, parse_name, WorldData};
+
+mod data;
+
+const TTL: u32 = 3600;
+const DELEGATION_TTL: u32 = 172800;
+
+/// A zone that some server is authoritative for.
+struct Zone {
+    origin: Name,
+    records: Vec<Record>,
+}
+
+impl Zone {
+    fn new(origin: Name) -> Self {
+        Self {
+            origin,
+            records: vec![],
+        }
+    }
+
+    fn add(&mut self, name: &Name, ttl: u32, rdata: RData) {
+        self.records
+            .push(Record::from_rdata(name.clone(), ttl, rdata));
+    }
+
+    fn records_at<'a>(&'a self, name: &'a Name) -> impl Iterator<Item = &'a Record> + 'a {
+        self.records.iter().filter(move |r| r.name() == name)
+    }
+
+    fn soa(&self) -> impl Iterator<Item = &Record> {
+        self.records_at(&self.origin)
+            .filter(|r| r.record_type() == RecordType::SOA)
+    }
+
+    /// Find the delegation point (a name with NS records below the origin)
+    /// that covers the given name, if there is one.
+    fn delegation_for(&self, name: &Name) -> Option<Name> {
+        let mut cut = name.clone();
+        while cut.num_labels() > self.origin.num_labels() {
+            if self
+                .records_at(&cut)
+                .any(|r| r.record_type() == RecordType::NS)
+            {
+                return Some(cut);
+            }
+            cut = cut.base_name();
+        }
+        None
+    }
+
+    /// Whether any record exists at or below this name.
+    fn has_name(&self, name: &Name) -> bool {
+        self.records.iter().any(|r| name.zone_of(r.name()))
+    }
+}
+
+/// A virtual name server.
+struct Server {
+    /// Origins of the zones this server is authoritative for.
+    zones: Vec<Name>,
+}
+
+/// The whole simulated DNS hierarchy.
+pub struct World {
+    servers: HashMap<IpAddr, Server>,
+    zones: HashMap<Name, Zone>,
+    /// Addresses of every server host name, used to fill in glue records.
+    hosts: HashMap<Name, Ipv4Addr>,
+    root_servers: Vec<Name>,
+    /// Second-level domains that have zones, in the order they were generated.
+    domains: Vec<Name>,
+    seed: Vec<u8>,
+}
+
+impl World {
+    /// Build the world from the given data.
+    /// The same seed always produces the same world, down to every address and record.
+    pub fn generate(data: &WorldData, seed: &[u8]) -> Self {
+        let mut gen = Generator {
+            seed: seed.to_vec(),
+            used: HashSet::new(),
+        };
+        let mut world = World {
+            servers: HashMap::new(),
+            zones: HashMap::new(),
+            hosts: HashMap::new(),
+            root_servers: data.root_servers.clone(),
+            domains: vec![],
+            seed: seed.to_vec(),
+        };
+
+        let mut root = Zone::new(Name::root());
+        root.add(
+            &Name::root(),
+            86400,
+            soa_for(&Name::root(), &data.root_servers[0]),
+        );
+        for server in &data.root_servers {
+            root.add(&Name::root(), 518400, RData::NS(server.clone()));
+            world.add_server(&mut gen, server, &Name::root());
+        }
+
+        for (tld, servers) in &data.tlds {
+            let mut zone = Zone::new(tld.clone());
+            zone.add(tld, 900, soa_for(tld, &servers[0]));
+            for server in servers {
+                root.add(tld, DELEGATION_TTL, RData::NS(server.clone()));
+                zone.add(tld, DELEGATION_TTL, RData::NS(server.clone()));
+                world.add_server(&mut gen, server, tld);
+            }
+            world.zones.insert(tld.clone(), zone);
+        }
+        world.zones.insert(Name::root(), root);
+
+        for domain in &data.domains {
+            let tld = domain.base_name();
+            if !world.zones.contains_key(&tld) {
+                continue;
+            }
+            let zone = world.generate_domain(&mut gen, domain);
+            let parent = world.zones.get_mut(&tld).unwrap();
+            for ns in zone.records_at(domain) {
+                if ns.record_type() == RecordType::NS {
+                    if let Some(rdata) = ns.data() {
+                        parent.add(domain, DELEGATION_TTL, rdata.clone());
+                    }
+                }
+            }
+            world.zones.insert(domain.clone(), zone);
+            world.domains.push(domain.clone());
+        }
+
+        world
+    }
+
+    /// Register a server host serving the given zone, allocating it an address if needed.
+    fn add_server(&mut self, gen: &mut Generator, host: &Name, zone: &Name) {
+        let addr = *self
+            .hosts
+            .entry(host.clone())
+            .or_insert_with(|| gen.address_for(host));
+        self.servers
+            .entry(IpAddr::V4(addr))
+            .or_insert_with(|| Server { zones: vec![] })
+            .zones
+            .push(zone.clone());
+    }
+
+    /// Fill in the zone of a second-level domain:
+    /// name servers, web server addresses, mail exchangers and an SPF policy.
+    fn generate_domain(&mut self, gen: &mut Generator, domain: &Name) -> Zone {
+        let mut rng = gen.rng_for(&domain.to_string());
+        let mut zone = Zone::new(domain.clone());
+        let sub = |label: &str| {
+            Name::from_ascii(label)
+                .unwrap()
+                .append_domain(domain)
+                .unwrap()
+        };
+
+        let ns_hosts: Vec<Name> = (1..=rng.gen_range(2..=3))
+            .map(|i| sub(&format!("ns{i}")))
+            .collect();
+        zone.add(domain, TTL, soa_for(domain, &ns_hosts[0]));
+        for host in &ns_hosts {
+            zone.add(domain, TTL, RData::NS(host.clone()));
+            self.add_server(gen, host, domain);
+            zone.add(host, TTL, RData::A(self.hosts[host]));
+        }
+
+        for _ in 0..rng.gen_range(1..=2) {
+            zone.add(domain, 300, RData::A(gen.address_for_host(&mut rng)));
+        }
+        let www = sub("www");
+        if rng.gen_bool(0.5) {
+            zone.add(&www, 300, RData::CNAME(domain.clone()));
+        } else {
+            zone.add(&www, 300, RData::A(gen.address_for_host(&mut rng)));
+        }
+
+        let mut spf = String::from("v=spf1");
+        for i in 1..=rng.gen_range(1..=2) {
+            let mx = sub(&format!("mx{i}"));
+            let addr = gen.address_for_host(&mut rng);
+            zone.add(domain, TTL, RData::MX(MX::new(i * 10, mx.clone())));
+            zone.add(&mx, TTL, RData::A(addr));
+            spf.push_str(&format!(" ip4:{addr}"));
+        }
+        spf.push_str(" -all");
+        zone.add(domain, TTL, RData::TXT(TXT::new(vec![spf])));
+
+        zone
+    }
+
+    /// Second-level domains of the world, such as `wikipedia.org.`.
+    pub fn domains(&self) -> &[Name] {
+        &self.domains
+    }
+
+    /// A random number generator tied to this world and the given label,
+    /// for deriving other things from the same seed, such as questions.
+    pub fn rng_for(&self, label: &str) -> ChaCha8Rng {
+        seeded_rng(&self.seed, label)
+    }
+
+    /// Addresses of the root servers, which are the starting point for every resolution.
+    pub fn root_server_addresses(&self) -> Vec<Ipv4Addr> {
+        self.root_servers.iter().map(|s| self.hosts[s]).collect()
+    }
+
+    /// Ask the server at the given address a question.
+    /// Addresses where there is no server behave as if the packet was lost.
+    pub fn query(
+        &self,
+        server: IpAddr,
+        name: &Name,
+        query_type: RecordType,
+    ) -> Result<Message, QueryError> {
+        let server = self.servers.get(&server).ok_or(QueryError::Timeout)?;
+        let name = name.to_lowercase();
+
+        let mut msg = Message::new();
+        msg.set_id(rand::random())
+            .set_message_type(MessageType::Response)
+            .set_op_code(OpCode::Query)
+            .set_recursion_desired(true)
+            .set_recursion_available(false)
+            .add_query(Query::query(name.clone(), query_type));
+        self.answer_from(server, &name, query_type, &mut msg);
+
+        // Send the message through the wire format, like a real response would be.
+        // This also fills in the section counts in the header.
+        let wire = msg.to_vec().unwrap(); // infallible for the messages we build
+        Ok(Message::from_vec(&wire).unwrap())
+    }
+
+    /// Fill in the response of a server to a query.
+    fn answer_from(&self, server: &Server, name: &Name, query_type: RecordType, msg: &mut Message) {
+        // The most specific zone this server has for the name.
+        let zone = server
+            .zones
+            .iter()
+            .filter(|origin| origin.zone_of(name))
+            .max_by_key(|origin| origin.num_labels())
+            .map(|origin| &self.zones[origin]);
+        let Some(zone) = zone else {
+            msg.set_response_code(ResponseCode::Refused);
+            return;
+        };
+
+        if let Some(cut) = zone.delegation_for(name) {
+            // Not ours: refer the client to the servers of the child zone.
+            let ns: Vec<Record> = zone
+                .records_at(&cut)
+                .filter(|r| r.record_type() == RecordType::NS)
+                .cloned()
+                .collect();
+            self.add_glue(msg, &ns);
+            msg.insert_name_servers(ns);
+            return;
+        }
+
+        msg.set_authoritative(true);
+        let at_name: Vec<&Record> = zone.records_at(name).collect();
+        let answers: Vec<Record> = at_name
+            .iter()
+            .filter(|r| query_type == RecordType::ANY || r.record_type() == query_type)
+            .map(|r| (*r).clone())
+            .collect();
+        if !answers.is_empty() {
+            self.add_glue(msg, &answers);
+            msg.insert_answers(answers);
+        } else if let Some(cname) = at_name
+            .iter()
+            .find(|r| r.record_type() == RecordType::CNAME)
+        {
+            msg.add_answer((*cname).clone());
+            if let Some(RData::CNAME(target)) = cname.data() {
+                // Chase the alias if its target is in the same zone.
+                msg.add_answers(
+                    zone.records_at(target)
+                        .filter(|r| r.record_type() == query_type)
+                        .cloned(),
+                );
+            }
+        } else {
+            if !zone.has_name(name) {
+                msg.set_response_code(ResponseCode::NXDomain);
+            }
+            msg.add_name_servers(zone.soa().cloned());
+        }
+    }
+
+    /// Add the addresses of the hosts named by NS and MX records to the additional section.
+    fn add_glue(&self, msg: &mut Message, records: &[Record]) {
+        for record in records {
+            let host = match record.data() {
+                Some(RData::NS(host)) => host,
+                Some(RData::MX(mx)) => mx.exchange(),
+                _ => continue,
+            };
+            if let Some(addr) = self.address_of(host) {
+                msg.add_additional(Record::from_rdata(host.clone(), TTL, RData::A(addr)));
+            }
+        }
+    }
+
+    fn address_of(&self, host: &Name) -> Option<Ipv4Addr> {
+        if let Some(addr) = self.hosts.get(host) {
+            return Some(*addr);
+        }
+        let zone = self.zones.get(&host.base_name())?;
+        zone.records_at(host).find_map(|r| match r.data() {
+            Some(RData::A(addr)) => Some(*addr),
+            _ => None,
+        })
+    }
+
+    /// Whether the given name belongs to the simulated world,
+    /// as opposed to some name in a TLD that the world has no data for.
+    pub fn knows(&self, name: &Name) -> bool {
+        let name = name.to_lowercase();
+        if name.num_labels() <= 1 {
+            return name.is_root() || self.zones.contains_key(&name);
+        }
+        self.zones.contains_key(&name.trim_to(2))
+    }
+
+    /// Resolve a name iteratively, starting from a root server, like a recursive resolver would.
+    /// Returns `None` if the name does not exist or has no records of this type.
+    pub fn resolve(&self, name: &Name, query_type: RecordType) -> Option<Vec<Record>> {
+        let last = self.resolution_path(name, query_type).pop()?;
+        let matching: Vec<Record> = last
+            .response
+            .answers()
+            .iter()
+            .filter(|r| r.record_type() == query_type)
+            .cloned()
+            .collect();
+        (!matching.is_empty()).then_some(matching)
+    }
+
+    /// The queries that a recursive resolver would make to resolve a name, in order.
+    /// It starts from a root server, follows referrals using their glue records,
+    /// and starts over from the root for the target of an alias.
+    /// The last step has the answer, if the name could be resolved.
+    pub fn resolution_path(&self, name: &Name, query_type: RecordType) -> Vec<ResolutionStep> {
+        let mut steps = vec![];
+        let Some(root) = self.root_server_addresses().first().copied() else {
+            return steps;
+        };
+        let mut name = name.to_lowercase();
+        let mut server = IpAddr::V4(root);
+        // Guard against referral or alias loops.
+        for _ in 0..16 {
+            let Ok(response) = self.query(server, &name, query_type) else {
+                break;
+            };
+            let next = if response.response_code() != ResponseCode::NoError
+                || response
+                    .answers()
+                    .iter()
+                    .any(|r| r.record_type() == query_type)
+            {
+                None
+            } else if let Some(RData::CNAME(target)) =
+                response.answers().first().and_then(|r| r.data())
+            {
+                Some((target.clone(), IpAddr::V4(root)))
+            } else {
+                response
+                    .additionals()
+                    .iter()
+                    .find_map(|r| match r.data() {
+                        Some(RData::A(addr)) => Some((name.clone(), IpAddr::V4(*addr))),
+                        _ => None,
+                    })
+            };
+            steps.push(ResolutionStep {
+                server,
+                name: name.clone(),
+                query_type,
+                response,
+            });
+            let Some((next_name, next_server)) = next else {
+                break;
+            };
+            name = next_name;
+            server = next_server;
+        }
+        steps
+    }
+}
+
+/// One query made while resolving a name.
+pub struct ResolutionStep {
+    pub server: IpAddr,
+    pub name: Name,
+    pub query_type: RecordType,
+    pub response: Message,
+}
+
+fn soa_for(zone: &Name, primary: &Name) -> RData {
+    let hostmaster = Name::from_ascii("hostmaster")
+        .unwrap()
+        .append_domain(zone)
+        .unwrap();
+    RData::SOA(SOA::new(
+        primary.clone(),
+        hostmaster,
+        2023030100,
+        1800,
+        900,
+        604800,
+        86400,
+    ))
+}
+
+/// The seed of the world a particular session lives in:
+/// the length of the server secret, the secret, and the session key.
+/// The length keeps different secrets and keys from adding up to the same seed.
+///
+/// Knowing the key alone is not enough to reproduce someone's world,
+/// but the teacher, who has the secret, can regenerate it exactly.
+pub fn session_seed(secret: &[u8], key: &str) -> Vec<u8> {
+    let mut seed = (secret.len() as u64).to_le_bytes().to_vec();
+    seed.extend_from_slice(secret);
+    seed.extend_from_slice(key.as_bytes());
+    seed
+}
+
+/// A world that is being generated, or has been.
+type CachedWorld = Arc<OnceCell<Arc<World>>>;
+
+/// Generates the worlds of sessions on demand,
+/// keeping the most recently used ones around since generation is not cheap.
+pub struct WorldCache {
+    data: Arc<WorldData>,
+    secret: Vec<u8>,
+    capacity: usize,
+    recent: Mutex<VecDeque<(String, CachedWorld)>>,
+}
+
+impl WorldCache {
+    pub fn new(data: WorldData, secret: Vec<u8>, capacity: usize) -> Self {
+        Self {
+            data: Arc::new(data),
+            secret,
+            capacity,
+            recent: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    /// The world that the session with this key lives in.
+    ///
+    /// A missing world is generated on a blocking thread, without holding up requests to other sessions;
+    /// requests to the same session wait for it to be generated once.
+    pub async fn get(&self, key: &str) -> Arc<World> {
+        let cell = {
+            let mut recent = self.recent.lock().unwrap();
+            let entry = match recent.iter().position(|(k, _)| k == key) {
+                Some(pos) => recent.remove(pos).unwrap(),
+                None => (key.to_owned(), CachedWorld::default()),
+            };
+            let cell = entry.1.clone();
+            recent.push_front(entry);
+            recent.truncate(self.capacity.max(1));
+            cell
+        };
+        cell.get_or_init(|| {
+            let data = self.data.clone();
+            let seed = session_seed(&self.secret, key);
+            async move {
+                tokio::task::spawn_blocking(move || Arc::new(World::generate(&data, &seed)))
+                    .await
+                    .expect("world generation panicked")
+            }
+        })
+        .await
+        .clone()
+    }
+}
+
+/// A random address that looks like an ordinary public unicast address.
+pub fn random_public_address(rng: &mut impl Rng) -> Ipv4Addr {
+    loop {
+        let addr = Ipv4Addr::from(rng.gen::<u32>());
+        let [a, b, ..] = addr.octets();
+        let special = a == 0
+            || a == 10
+            || a == 127
+            || a >= 224
+            || (a == 100 && (64..128).contains(&b))
+            || (a == 169 && b == 254)
+            || (a == 172 && (16..32).contains(&b))
+            || (a == 192 && b == 168);
+        if !special {
+            return addr;
+        }
+    }
+}
+
+fn seeded_rng(seed: &[u8], label: &str) -> ChaCha8Rng {
+    let mut hasher = Sha256::new();
+    // The length keeps different seeds and labels from adding up to the same input.
+    hasher.update((seed.len() as u64).to_le_bytes());
+    hasher.update(seed);
+    hasher.update(label.as_bytes());
+    ChaCha8Rng::from_seed(hasher.finalize().into())
+}
+
+/// Hands out made-up addresses, making sure no two hosts share one.
+struct Generator {
+    seed: Vec<u8>,
+    used: HashSet<Ipv4Addr>,
+}
+
+impl Generator {
+    /// A random number generator initialized with the world's seed and the given label.
+    /// It always produces the same values for the same seed and label.
+    fn rng_for(&self, label: &str) -> ChaCha8Rng {
+        seeded_rng(&self.seed, label)
+    }
+
+    /// The address of a server host, derived from its name.
+    fn address_for(&mut self, host: &Name) -> Ipv4Addr {
+        let mut rng = self.rng_for(&host.to_string());
+        self.address_for_host(&mut rng)
+    }
+
+    /// A fresh address that no other host in this world has.
+    fn address_for_host(&mut self, rng: &mut ChaCha8Rng) -> Ipv4Addr {
+        loop {
+            let addr = random_public_address(rng);
+            if self.used.insert(addr) {
+                return addr;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn name(s: &str) -> Name {
+        parse_name(s).unwrap()
+    }
+
+    #[test]
+    fn test_referral_chain() {
+        let world = World::generate(&WorldData::builtin(), b"test");
+        let root = IpAddr::V4(world.root_server_addresses()[0]);
+
+        let resp = world
+            .query(root, &name("www.wikipedia.org"), RecordType::A)
+            .unwrap();
+        assert!(!resp.authoritative());
+        assert!(resp.answers().is_empty());
+        assert!(resp.name_servers().iter().all(|r| r.name() == &name("org")));
+        assert!(!resp.additionals().is_empty());
+
+        let answer = world
+            .resolve(&name("wikipedia.org"), RecordType::A)
+            .unwrap();
+        assert!(!answer.is_empty());
+        let mx = world.resolve(&name("gmail.com"), RecordType::MX).unwrap();
+        assert!(!mx.is_empty());
+
+        // Root, then the TLD, then the domain's own servers.
+        let path = world.resolution_path(&name("www.wikipedia.org"), RecordType::A);
+        assert_eq!(path.len(), 3);
+        assert_eq!(path[0].server, root);
+        assert!(path.iter().all(|step| step.name == name("www.wikipedia.org")));
+        assert!(path[2].response.authoritative());
+        assert!(!path[2].response.answers().is_empty());
+    }
+
+    #[test]
+    fn test_nxdomain_and_missing_servers() {
+        let world = World::generate(&WorldData::builtin(), b"test");
+        let root = IpAddr::V4(world.root_server_addresses()[0]);
+
+        let resp = world
+            .query(root, &name("example.invalid"), RecordType::A)
+            .unwrap();
+        assert_eq!(resp.response_code(), ResponseCode::NXDomain);
+        assert!(resp.authoritative());
+
+        assert!(matches!(
+            world.query(
+                "192.0.2.1".parse().unwrap(),
+                &name("example.com"),
+                RecordType::A
+            ),
+            Err(QueryError::Timeout)
+        ));
+    }
+
+    #[test]
+    fn test_generation_is_seeded() {
+        let data = WorldData::builtin();
+        let alice = World::generate(&data, &session_seed(b"secret", "alice"));
+        let alice_again = World::generate(&data, &session_seed(b"secret", "alice"));
+        let bob = World::generate(&data, &session_seed(b"secret", "bob"));
+        let alice_other_secret = World::generate(&data, &session_seed(b"other", "alice"));
+
+        assert_eq!(alice.hosts, alice_again.hosts);
+        assert_eq!(
+            alice.resolve(&name("gmail.com"), RecordType::MX),
+            alice_again.resolve(&name("gmail.com"), RecordType::MX)
+        );
+        assert_ne!(alice.root_server_addresses(), bob.root_server_addresses());
+        assert_ne!(
+            alice.root_server_addresses(),
+            alice_other_secret.root_server_addresses()
+        );
+        // Moving a character from the end of the secret to the start of the key makes another world.
+        assert_ne!(
+            session_seed(b"secret", "alice"),
+            session_seed(b"secre", "talice")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_world_cache() {
+        let cache = WorldCache::new(WorldData::builtin(), b"secret".to_vec(), 2);
+        let (alice, alice_again) = tokio::join!(cache.get("alice"), cache.get("alice"));
+        assert!(Arc::ptr_eq(&alice, &alice_again));
+        assert_eq!(
+            alice.hosts,
+            World::generate(&WorldData::builtin(), &session_seed(b"secret", "alice")).hosts
+        );
+
+        cache.get("bob").await;
+        cache.get("carol").await;
+        // Alice was used the longest time ago, so her world was dropped and made anew.
+        assert!(!Arc::ptr_eq(&alice, &cache.get("alice").await));
+    }
+}
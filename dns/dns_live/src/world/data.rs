@@ -0,0 +1,192 @@
+use std::{collections::BTreeMap, path::Path};
+
+use trust_dns_client::rr::Name;
+
+/// How many second-level domains to take from the domain list.
+/// The full Majestic Million is far too large to simulate.
+pub const DOMAIN_LIMIT: usize = 1000;
+
+/// The raw material the simulated world is built from:
+/// the list of TLDs with their name servers, and a list of second-level domains.
+pub struct WorldData {
+    /// Names of the root servers.
+    pub root_servers: Vec<Name>,
+    /// TLDs and the host names of their name servers.
+    pub tlds: BTreeMap<Name, Vec<Name>>,
+    /// Second-level domains, most popular first.
+    pub domains: Vec<Name>,
+}
+
+impl WorldData {
+    /// Load the world data from the files produced by `make update` in the `dns` directory.
+    /// If any of them is missing or unreadable, a small built-in dataset is used instead.
+    pub fn load(root_zone: &Path, domain_list: &Path) -> Self {
+        let root_zone = match std::fs::read_to_string(root_zone) {
+            Ok(data) => data,
+            Err(e) => {
+                tracing::warn!(
+                    "Could not read root zone from {root_zone:?} ({e}), using built-in world data"
+                );
+                return Self::builtin();
+            }
+        };
+        let domain_list = match std::fs::read_to_string(domain_list) {
+            Ok(data) => data,
+            Err(e) => {
+                tracing::warn!(
+                    "Could not read domain list from {domain_list:?} ({e}), using built-in world data"
+                );
+                return Self::builtin();
+            }
+        };
+        let mut data = Self::parse_root_zone(&root_zone);
+        data.domains = parse_domain_list(&domain_list, &data.tlds, DOMAIN_LIMIT);
+        tracing::info!(
+            "Loaded world data: {} TLDs, {} domains",
+            data.tlds.len(),
+            data.domains.len()
+        );
+        data
+    }
+
+    /// Parse the NS records of the (cleaned) root zone file.
+    fn parse_root_zone(text: &str) -> Self {
+        let mut root_servers = vec![];
+        let mut tlds: BTreeMap<Name, Vec<Name>> = BTreeMap::new();
+        for line in text.lines() {
+            // <owner> <ttl> IN NS <target>
+            let parts: Vec<&str> = line.split_whitespace().collect();
+            if parts.len() != 5 || parts[2] != "IN" || parts[3] != "NS" {
+                continue;
+            }
+            let (Some(owner), Some(target)) = (parse_name(parts[0]), parse_name(parts[4])) else {
+                continue;
+            };
+            if owner.is_root() {
+                root_servers.push(target);
+            } else if owner.num_labels() == 1 {
+                tlds.entry(owner).or_default().push(target);
+            }
+        }
+        Self {
+            root_servers,
+            tlds,
+            domains: vec![],
+        }
+    }
+
+    /// A tiny world that is used when the real data files are not available.
+    pub fn builtin() -> Self {
+        let root_servers = "abcdefghijklm"
+            .chars()
+            .map(|c| parse_name(&format!("{c}.root-servers.net.")).unwrap())
+            .collect();
+        let tld_servers: &[(&str, &[&str])] = &[
+            (
+                "com",
+                &[
+                    "a.gtld-servers.net",
+                    "b.gtld-servers.net",
+                    "c.gtld-servers.net",
+                ],
+            ),
+            (
+                "net",
+                &[
+                    "a.gtld-servers.net",
+                    "b.gtld-servers.net",
+                    "c.gtld-servers.net",
+                ],
+            ),
+            (
+                "org",
+                &["a0.org.afilias-nst.info", "b0.org.afilias-nst.org"],
+            ),
+            (
+                "ru",
+                &["a.dns.ripn.net", "b.dns.ripn.net", "d.dns.ripn.net"],
+            ),
+            ("io", &["a0.nic.io", "b0.nic.io"]),
+            (
+                "info",
+                &["a0.info.afilias-nst.info", "b0.info.afilias-nst.org"],
+            ),
+        ];
+        let tlds = tld_servers
+            .iter()
+            .map(|(tld, servers)| {
+                (
+                    parse_name(tld).unwrap(),
+                    servers.iter().map(|s| parse_name(s).unwrap()).collect(),
+                )
+            })
+            .collect();
+        let domains = [
+            "google.com",
+            "wikipedia.org",
+            "yandex.ru",
+            "gmail.com",
+            "github.com",
+            "mail.ru",
+            "vk.com",
+            "rudn.ru",
+            "habr.com",
+            "example.net",
+            "github.io",
+            "iana.org",
+        ]
+        .iter()
+        .map(|d| parse_name(d).unwrap())
+        .collect();
+        Self {
+            root_servers,
+            tlds,
+            domains,
+        }
+    }
+}
+
+/// Load the server-side secret produced by `make secret` in the `dns` directory.
+///
+/// Without it, anyone who knows a session key can reproduce that session's world,
+/// so a missing or empty secret is an error unless `allow_missing` is set.
+pub fn load_secret(path: &Path, allow_missing: bool) -> Result<Vec<u8>, String> {
+    let problem = match std::fs::read(path) {
+        Ok(secret) if !secret.is_empty() => return Ok(secret),
+        Ok(_) => "the file is empty".to_owned(),
+        Err(e) => e.to_string(),
+    };
+    if allow_missing {
+        tracing::warn!(
+            "Could not read secret from {path:?} ({problem}), worlds will be predictable from session keys alone"
+        );
+        return Ok(vec![]);
+    }
+    Err(format!(
+        "Could not read secret from {path:?} ({problem}); create it with `make secret` in the dns directory, \
+         or pass --allow-missing-secret to make worlds predictable from session keys alone"
+    ))
+}
+
+/// Parse the Majestic Million CSV, keeping the first `limit` second-level domains
+/// whose TLD is known.
+fn parse_domain_list(text: &str, tlds: &BTreeMap<Name, Vec<Name>>, limit: usize) -> Vec<Name> {
+    text.lines()
+        .skip(1) // header
+        .filter_map(|line| line.split(',').nth(2))
+        .filter_map(parse_name)
+        .filter(|name| name.num_labels() == 2 && tlds.contains_key(&name.base_name()))
+        .take(limit)
+        .collect()
+}
+
+/// Parse a domain name, making it fully qualified and lowercase.
+pub fn parse_name(text: &str) -> Option<Name> {
+    let text = text.trim();
+    let name = if text.ends_with('.') {
+        Name::from_ascii(text)
+    } else {
+        Name::from_ascii(format!("{text}."))
+    };
+    name.ok().map(|name| name.to_lowercase())
+}